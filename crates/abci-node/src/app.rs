@@ -1,13 +1,20 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use anyhow::Result;
-use tracing::info;
+use tracing::{error, info};
 
 use tendermint_abci::Application;
 use tendermint_proto::abci;
 use tendermint::block::Header as TmHeader;
 
-use crate::exec::{BlockExec, RethCtx};
-use crate::wire::{decode_eth_tx, apphash_from};
+use crate::chain::ChainOptions;
+use crate::config::{PruningConfig, SnapshotConfig};
+use crate::exec::{BlockExec, BlockInfo, ExecError, RethCtx};
+use crate::query;
+#[cfg(feature = "with-reth")]
+use crate::snapshot::{self, Restore, SnapshotInfo, SnapshotPayload};
+use crate::store::{BlockMeta, BlockParams};
+use crate::wire::decode_eth_tx;
 
 #[derive(Clone)]
 pub struct EvmAbciApp {
@@ -18,21 +25,80 @@ pub struct State {
     reth: RethCtx,
     height: i64,
     last_app_hash: [u8; 32],
+    last_block_hash: [u8; 32],
     in_block: Option<BlockExec>,
+    // Blocks executed in ProcessProposal at the current height, by block hash
+    proposals: HashMap<[u8; 32], BlockExec>,
+    // Block params sent in EndBlock, in force once this block commits
+    params_update: Option<BlockParams>,
+    // State-sync snapshot being restored
+    #[cfg(feature = "with-reth")]
+    restore: Option<Restore>,
 }
 
 impl EvmAbciApp {
-    pub fn boot(path: &str) -> Result<Self> {
+    pub fn boot(
+        path: &str,
+        chain: ChainOptions,
+        snapshot: SnapshotConfig,
+        pruning: PruningConfig,
+    ) -> Result<Self> {
         info!("Booting EVM ABCI app with data path: {}", path);
-        let reth = RethCtx::open(path)?;
+        let reth = RethCtx::open(path, chain)?.with_pruning_config(pruning);
+        #[cfg(feature = "with-reth")]
+        let reth = reth.with_snapshot_config(snapshot)?;
+        #[cfg(not(feature = "with-reth"))]
+        let _ = snapshot;
+        let last = reth.store.latest()?.unwrap_or_default();
+        info!(
+            "Resuming at height {} with app hash {}",
+            last.height,
+            hex::encode(last.app_hash)
+        );
         let inner = Arc::new(Mutex::new(State {
             reth,
-            height: 0,
-            last_app_hash: [0u8; 32],
+            height: last.height,
+            last_app_hash: last.app_hash,
+            last_block_hash: last.hash,
             in_block: None,
+            proposals: HashMap::new(),
+            params_update: None,
+            #[cfg(feature = "with-reth")]
+            restore: None,
         }));
         Ok(Self { inner })
     }
+
+    // Handle on the app's execution context, for serving reads outside ABCI
+    pub fn reth(&self) -> RethCtx {
+        self.inner.lock().unwrap().reth.clone()
+    }
+}
+
+impl State {
+    // Commit the executed block and move on to it. Errors are state
+    // failures; the caller must halt.
+    fn commit_block(&mut self, exec: BlockExec) -> Result<BlockMeta, ExecError> {
+        let block_params = self.params_update.take().unwrap_or_else(|| self.reth.block_params());
+        let meta = self.reth.commit_block(exec, block_params)?;
+
+        self.last_app_hash = meta.app_hash;
+        self.last_block_hash = meta.hash;
+        self.height = meta.height;
+        self.reth.reset_pending();
+        Ok(meta)
+    }
+
+    // Adopt a verified snapshot as the last committed block
+    #[cfg(feature = "with-reth")]
+    fn restore_from(&mut self, payload: SnapshotPayload) -> Result<()> {
+        let meta = payload.meta.clone();
+        self.reth.restore_snapshot(payload)?;
+        self.height = meta.height;
+        self.last_app_hash = meta.app_hash;
+        self.last_block_hash = meta.hash;
+        Ok(())
+    }
 }
 
 impl Application for EvmAbciApp {
@@ -49,41 +115,96 @@ impl Application for EvmAbciApp {
     }
 
     fn init_chain(&self, req: abci::RequestInitChain) -> abci::ResponseInitChain {
+        let mut st = self.inner.lock().unwrap();
         info!("Initializing chain with {} validators", req.validators.len());
-        Default::default()
+
+        if let Some(block) = req.consensus_params.as_ref().and_then(|p| p.block.as_ref()) {
+            let params = BlockParams {
+                max_bytes: block.max_bytes,
+                max_gas: block.max_gas,
+            };
+            if let Err(e) = st.reth.set_block_params(params) {
+                let e = ExecError::State(e.context("Failed to store consensus block params"));
+                halt(req.initial_height, &e);
+            }
+            info!("Block gas limit: {}", st.reth.block_gas_limit());
+        }
+
+        match st.reth.init_genesis(&req.app_state_bytes) {
+            Ok(Some(app_hash)) => {
+                info!("Loaded genesis state - app hash: {}", hex::encode(app_hash));
+                st.last_app_hash = app_hash;
+                abci::ResponseInitChain {
+                    app_hash: app_hash.to_vec().into(),
+                    ..Default::default()
+                }
+            }
+            Ok(None) => Default::default(),
+            Err(e) => {
+                // Nothing sensible to run without the intended genesis
+                let e = ExecError::State(e.context("Failed to load genesis from app_state_bytes"));
+                halt(req.initial_height, &e);
+            }
+        }
     }
 
     fn begin_block(&self, req: abci::RequestBeginBlock) -> abci::ResponseBeginBlock {
         let mut st = self.inner.lock().unwrap();
 
+        // Without a block to execute, every DeliverTx and the Commit of a
+        // block CometBFT decided would go wrong, so stop here
         let Some(proto_header) = req.header else {
-            info!("Received begin_block without header — skipping block setup.");
-            return Default::default();
+            let e = ExecError::State(anyhow::anyhow!("BeginBlock without a header"));
+            halt(st.height + 1, &e);
         };
 
-        let Ok(header) = TmHeader::try_from(proto_header) else {
-            info!("Invalid header in begin_block — skipping.");
-            return Default::default();
+        let height = proto_header.height;
+        let header = match TmHeader::try_from(proto_header) {
+            Ok(header) => header,
+            Err(e) => {
+                let e = ExecError::State(anyhow::anyhow!("Invalid header in BeginBlock: {}", e));
+                halt(height, &e);
+            }
         };
 
         info!("Beginning block at height {}", header.height);
-        st.in_block = Some(BlockExec::new(&st.reth, header));
+        let block = BlockInfo::from_header(&header);
+        let exec = match st.proposals.remove(&block.hash) {
+            Some(exec) => {
+                info!("Reusing execution from ProcessProposal for {}", hex::encode(block.hash));
+                exec
+            }
+            None => BlockExec::new(&st.reth, block),
+        };
+        st.proposals.clear();
+        st.in_block = Some(exec);
         Default::default()
     }
 
     fn check_tx(&self, req: abci::RequestCheckTx) -> abci::ResponseCheckTx {
         let st = self.inner.lock().unwrap();
-        match decode_eth_tx(&req.tx).and_then(|etx| st.reth.validate_tx_basic(&etx)) {
-            Ok(_) => {
-                info!("CheckTx passed for tx");
+        let recheck = req.r#type == abci::CheckTxType::Recheck as i32;
+        let checked = decode_eth_tx(&req.tx).and_then(|etx| {
+            if recheck {
+                st.reth.recheck_tx(&etx)
+            } else {
+                st.reth.check_tx(&etx)
+            }
+        });
+        match checked {
+            Ok(checked) => {
+                info!("CheckTx passed for tx from {} nonce {}", checked.sender, checked.nonce);
                 abci::ResponseCheckTx {
                     code: 0,
-                    gas_wanted: 100_000,
+                    gas_wanted: checked.gas_wanted as i64,
+                    priority: checked.priority,
+                    sender: checked.sender,
+                    info: format!("nonce={}", checked.nonce),
                     ..Default::default()
                 }
             }
             Err(e) => {
-                info!("CheckTx failed: {}", e);
+                info!("CheckTx failed (recheck: {}): {}", recheck, e);
                 abci::ResponseCheckTx {
                     code: 1,
                     log: format!("{}", e),
@@ -98,9 +219,17 @@ impl Application for EvmAbciApp {
         req: abci::RequestPrepareProposal,
     ) -> abci::ResponsePrepareProposal {
         let st = self.inner.lock().unwrap();
-        info!("Preparing proposal with max {} bytes", req.max_tx_bytes);
-    
-        let out = st.reth.propose_block(req.max_tx_bytes as usize);
+        info!("Preparing proposal from {} txs with max {} bytes", req.txs.len(), req.max_tx_bytes);
+
+        let block = BlockInfo {
+            height: req.height,
+            timestamp: req.time.as_ref().map_or(0, |t| t.seconds as u64),
+            proposer: req.proposer_address.to_vec(),
+            hash: [0u8; 32],
+            parent_hash: st.last_block_hash,
+        };
+        let txs: Vec<Vec<u8>> = req.txs.iter().map(|b| b.to_vec()).collect();
+        let out = st.reth.propose_block(&txs, req.max_tx_bytes as usize, block);
     
         abci::ResponsePrepareProposal {
             txs: out.txs.into_iter().map(Into::into).collect(),
@@ -109,10 +238,29 @@ impl Application for EvmAbciApp {
     }
 
     fn process_proposal(&self, req: abci::RequestProcessProposal) -> abci::ResponseProcessProposal {
-        let st = self.inner.lock().unwrap();
+        let mut st = self.inner.lock().unwrap();
         let txs: Vec<Vec<u8>> = req.txs.iter().map(|b| b.to_vec()).collect();
-        let valid = st.reth.quick_validate_proposal(&txs);
-        info!("Processing proposal with {} txs - valid: {}", txs.len(), valid);
+        let block = BlockInfo {
+            height: req.height,
+            timestamp: req.time.as_ref().map_or(0, |t| t.seconds as u64),
+            proposer: req.proposer_address.to_vec(),
+            hash: req.hash.as_ref().try_into().unwrap_or_default(),
+            parent_hash: st.last_block_hash,
+        };
+        let valid = match st.reth.validate_proposal(&txs, block) {
+            Ok(exec) => {
+                info!("Processing proposal with {} txs - valid", txs.len());
+                // Kept for BeginBlock in case this proposal is decided
+                if exec.hash() != [0u8; 32] {
+                    st.proposals.insert(exec.hash(), exec);
+                }
+                true
+            }
+            Err(e) => {
+                info!("Processing proposal with {} txs - rejected: {}", txs.len(), e);
+                false
+            }
+        };
         abci::ResponseProcessProposal {
             status: if valid {
                 abci::response_process_proposal::ProposalStatus::Accept as i32
@@ -129,25 +277,30 @@ impl Application for EvmAbciApp {
         };
 
         let mut st = self.inner.lock().unwrap();
+        let height = st.height + 1;
         let Some(exec) = st.in_block.as_mut() else {
-            return abci::ResponseDeliverTx {
-                code: 2,
-                log: "No block in progress".into(),
-                ..Default::default()
-            };
+            // Rejecting the tx would give a DeliverTx result the rest of the
+            // network doesn't
+            let e = ExecError::State(anyhow::anyhow!("DeliverTx without a block in progress"));
+            halt(height, &e);
         };
 
-        match decode_eth_tx(&req.tx).and_then(|etx| exec.apply_tx(&reth, etx)) {
+        let result = decode_eth_tx(&req.tx)
+            .map_err(ExecError::Tx)
+            .and_then(|etx| exec.apply_tx(&reth, etx));
+        match result {
             Ok(receipt) => {
                 info!("Transaction executed successfully - gas used: {}", receipt.gas_used);
                 abci::ResponseDeliverTx {
                     code: 0,
-                    gas_wanted: 100_000,
+                    gas_wanted: receipt.gas_limit as i64,
                     gas_used: receipt.gas_used as i64,
+                    data: receipt.output.clone().into(),
                     events: receipt.into_abci_events(),
                     ..Default::default()
                 }
             }
+            Err(e) if e.is_fatal() => halt(exec.height(), &e),
             Err(e) => {
                 info!("Transaction failed: {}", e);
                 abci::ResponseDeliverTx {
@@ -160,84 +313,245 @@ impl Application for EvmAbciApp {
     }
 
     fn end_block(&self, req: abci::RequestEndBlock) -> abci::ResponseEndBlock {
+        let mut st = self.inner.lock().unwrap();
         info!("Ending block at height {}", req.height);
-        Default::default()
+        if let Some(exec) = st.in_block.as_ref() {
+            info!(
+                "Block gas used: {}/{}, base fee: {} -> next: {}",
+                exec.gas_used(),
+                exec.gas_limit(),
+                exec.base_fee(),
+                exec.next_base_fee()
+            );
+        }
+
+        // Hand CometBFT the genesis gas limit if InitChain left max_gas unlimited
+        st.params_update = st.reth.block_params_update();
+        let Some(update) = st.params_update else {
+            return Default::default();
+        };
+        info!("Updating block max_gas to {}", update.max_gas);
+        abci::ResponseEndBlock {
+            consensus_param_updates: Some(tendermint_proto::types::ConsensusParams {
+                block: Some(tendermint_proto::types::BlockParams {
+                    max_bytes: update.max_bytes,
+                    max_gas: update.max_gas,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
     }
 
     fn commit(&self) -> abci::ResponseCommit {
         let mut st = self.inner.lock().unwrap();
         let Some(exec) = st.in_block.take() else {
-            info!("No block in progress during commit — returning previous app hash.");
-            return abci::ResponseCommit {
-                data: st.last_app_hash.to_vec().into(),
-                retain_height: st.height,
-            };
+            // BeginBlock never reached us, so CometBFT decided a block this
+            // node never executed
+            let e = ExecError::State(anyhow::anyhow!("No block in progress"));
+            halt(st.height + 1, &e);
         };
 
-        match exec.commit() {
-            Ok((state_root, receipts_root, gas_used, _ts)) => {
-                let app_hash = apphash_from(state_root, receipts_root);
-                st.last_app_hash = app_hash;
-                st.height += 1;
-
-                info!(
-                    "Committed block {} - gas used: {}, app hash: {}",
-                    st.height,
-                    gas_used,
-                    hex::encode(&app_hash)
-                );
-
-                abci::ResponseCommit {
-                    data: app_hash.to_vec().into(),
-                    retain_height: 0,
-                }
+        let height = exec.height();
+        let meta = match st.commit_block(exec) {
+            Ok(meta) => meta,
+            Err(e) => halt(height, &e),
+        };
+
+        // The block is durable from here on; what follows is housekeeping
+        #[cfg(feature = "with-reth")]
+        if let Err(e) = st.reth.maybe_snapshot(&meta) {
+            info!("Failed to take snapshot at height {}: {}", height, e);
+        }
+        let retain_height = st.reth.prune(height).unwrap_or_else(|e| {
+            info!("Failed to prune after height {}: {}", height, e);
+            0
+        });
+
+        info!(
+            "Committed block {} - gas used: {}, app hash: {}",
+            st.height,
+            meta.gas_used,
+            hex::encode(meta.app_hash)
+        );
+
+        abci::ResponseCommit {
+            data: meta.app_hash.to_vec().into(),
+            retain_height,
+        }
+    }
+
+    fn offer_snapshot(&self, req: abci::RequestOfferSnapshot) -> abci::ResponseOfferSnapshot {
+        use abci::response_offer_snapshot::Result as Offer;
+
+        #[cfg(feature = "with-reth")]
+        {
+            let mut st = self.inner.lock().unwrap();
+            let Some(snapshot) = req.snapshot else {
+                return abci::ResponseOfferSnapshot { result: Offer::Reject as i32 };
+            };
+            if !snapshot::is_supported(snapshot.format) {
+                return abci::ResponseOfferSnapshot { result: Offer::RejectFormat as i32 };
             }
-            Err(e) => {
-                info!("Failed to commit block: {}", e);
-                abci::ResponseCommit {
-                    data: st.last_app_hash.to_vec().into(),
-                    retain_height: st.height,
+            let Ok(app_hash) = <[u8; 32]>::try_from(req.app_hash.as_ref()) else {
+                return abci::ResponseOfferSnapshot { result: Offer::Reject as i32 };
+            };
+            let result = match SnapshotInfo::from_abci(&snapshot) {
+                Ok(info) => {
+                    info!("Accepted snapshot at height {} ({} chunks)", info.height, snapshot.chunks);
+                    st.restore = Some(Restore::new(info, app_hash));
+                    Offer::Accept
                 }
-            }
+                Err(e) => {
+                    info!("Rejected snapshot at height {}: {}", snapshot.height, e);
+                    Offer::Reject
+                }
+            };
+            abci::ResponseOfferSnapshot { result: result as i32 }
         }
-    }
 
-    fn offer_snapshot(&self, _req: abci::RequestOfferSnapshot) -> abci::ResponseOfferSnapshot {
-        abci::ResponseOfferSnapshot {
-            result: abci::response_offer_snapshot::Result::Reject as i32,
+        #[cfg(not(feature = "with-reth"))]
+        {
+            let _ = req;
+            abci::ResponseOfferSnapshot { result: Offer::Reject as i32 }
         }
     }
 
     fn list_snapshots(&self) -> abci::ResponseListSnapshots {
+        #[cfg(feature = "with-reth")]
+        {
+            let st = self.inner.lock().unwrap();
+            match st.reth.snapshots.store.list() {
+                Ok(infos) => abci::ResponseListSnapshots {
+                    snapshots: infos.iter().map(SnapshotInfo::to_abci).collect(),
+                },
+                Err(e) => {
+                    info!("Failed to list snapshots: {}", e);
+                    abci::ResponseListSnapshots { snapshots: vec![] }
+                }
+            }
+        }
+
+        #[cfg(not(feature = "with-reth"))]
         abci::ResponseListSnapshots { snapshots: vec![] }
     }
 
-    fn load_snapshot_chunk(&self, _req: abci::RequestLoadSnapshotChunk) -> abci::ResponseLoadSnapshotChunk {
-        abci::ResponseLoadSnapshotChunk {
-            chunk: vec![].into(),
+    fn load_snapshot_chunk(&self, req: abci::RequestLoadSnapshotChunk) -> abci::ResponseLoadSnapshotChunk {
+        #[cfg(feature = "with-reth")]
+        {
+            let st = self.inner.lock().unwrap();
+            let chunk = match st.reth.snapshots.store.chunk(req.height as i64, req.format, req.chunk) {
+                Ok(chunk) => chunk.unwrap_or_default(),
+                Err(e) => {
+                    info!("Failed to load snapshot {} chunk {}: {}", req.height, req.chunk, e);
+                    vec![]
+                }
+            };
+            abci::ResponseLoadSnapshotChunk { chunk: chunk.into() }
+        }
+
+        #[cfg(not(feature = "with-reth"))]
+        {
+            let _ = req;
+            abci::ResponseLoadSnapshotChunk { chunk: vec![].into() }
         }
     }
 
-    fn apply_snapshot_chunk(&self, _req: abci::RequestApplySnapshotChunk) -> abci::ResponseApplySnapshotChunk {
-        abci::ResponseApplySnapshotChunk {
-            result: abci::response_apply_snapshot_chunk::Result::Abort as i32,
-            refetch_chunks: vec![],
-            reject_senders: vec![],
+    // Chunks are checked against the offered chunk hashes as they arrive; a
+    // bad one is refetched from another peer. Once all are in, the state
+    // must hash to the trusted app hash or the whole snapshot is rejected
+    // along with everyone who sent parts of it.
+    fn apply_snapshot_chunk(&self, req: abci::RequestApplySnapshotChunk) -> abci::ResponseApplySnapshotChunk {
+        use abci::response_apply_snapshot_chunk::Result as Apply;
+
+        #[cfg(feature = "with-reth")]
+        {
+            let mut st = self.inner.lock().unwrap();
+            let Some(restore) = st.restore.as_mut() else {
+                return abci::ResponseApplySnapshotChunk {
+                    result: Apply::Abort as i32,
+                    ..Default::default()
+                };
+            };
+
+            match restore.add_chunk(req.index, req.chunk.to_vec(), req.sender.clone()) {
+                Ok(false) => abci::ResponseApplySnapshotChunk {
+                    result: Apply::Accept as i32,
+                    ..Default::default()
+                },
+                Ok(true) => {
+                    let restore = st.restore.take().unwrap();
+                    let restored = restore
+                        .finish(&st.reth.chain_opts)
+                        .and_then(|payload| st.restore_from(payload));
+                    match restored {
+                        Ok(()) => {
+                            info!("Restored snapshot at height {}", restore.height());
+                            abci::ResponseApplySnapshotChunk {
+                                result: Apply::Accept as i32,
+                                ..Default::default()
+                            }
+                        }
+                        Err(e) => {
+                            info!("Snapshot at height {} failed verification: {}", restore.height(), e);
+                            abci::ResponseApplySnapshotChunk {
+                                result: Apply::RejectSnapshot as i32,
+                                refetch_chunks: vec![],
+                                reject_senders: restore.senders(),
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    info!("Bad snapshot chunk {} from {}: {}", req.index, req.sender, e);
+                    abci::ResponseApplySnapshotChunk {
+                        result: Apply::Retry as i32,
+                        refetch_chunks: vec![req.index],
+                        reject_senders: vec![req.sender],
+                    }
+                }
+            }
+        }
+
+        #[cfg(not(feature = "with-reth"))]
+        {
+            let _ = req;
+            abci::ResponseApplySnapshotChunk {
+                result: Apply::Abort as i32,
+                refetch_chunks: vec![],
+                reject_senders: vec![],
+            }
         }
     }
 
     fn query(&self, req: abci::RequestQuery) -> abci::ResponseQuery {
-        info!("Query received for path: {}", req.path);
-        abci::ResponseQuery {
-            code: 0,
-            log: String::new(),
-            info: String::new(),
-            index: 0,
-            key: vec![].into(),
-            value: vec![].into(),
-            proof_ops: None,
-            height: 0,
-            codespace: String::new(),
-        }
-    }
-}
\ No newline at end of file
+        // Reads go to committed state and disk; don't hold the app lock
+        let reth = self.inner.lock().unwrap().reth.clone();
+        info!("Query received for path: {} at height {}", req.path, req.height);
+        match query::route(&reth, &req.path, req.height, req.prove) {
+            Ok(answer) => abci::ResponseQuery {
+                key: answer.key.into(),
+                value: answer.value.into(),
+                proof_ops: answer.proof_ops,
+                height: answer.height,
+                ..Default::default()
+            },
+            Err(e) => abci::ResponseQuery {
+                code: 1,
+                log: e.to_string(),
+                height: req.height,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+// Stop the node on a state failure. Answering CometBFT with the previous app
+// hash would fork this node off silently. The store writes latest.json last,
+// so on restart the node resumes at the last fully persisted block and
+// CometBFT replays the rest.
+fn halt(height: i64, e: &ExecError) -> ! {
+    error!("Halting at block {}: {}", height, e);
+    error!("Fix the cause and restart; the node resumes from its last persisted block");
+    std::process::exit(1)
+}
@@ -0,0 +1,123 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "with-reth")]
+use {
+    reth::{
+        chainspec::{ChainSpec, Genesis},
+        primitives::{Address, U256},
+    },
+    std::collections::{BTreeMap, HashMap},
+};
+
+// Where the chain spec comes from. A genesis file (or the genesis delivered
+// at InitChain) is the base; the explicit options override its config.
+#[derive(Args, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChainOptions {
+    /// Ethereum-style genesis JSON (chain config + alloc)
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genesis: Option<PathBuf>,
+
+    /// Override the EVM chain id
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+
+    /// Shanghai activation timestamp
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shanghai_time: Option<u64>,
+
+    /// Cancun activation timestamp
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cancun_time: Option<u64>,
+
+    /// Prague activation timestamp
+    #[arg(long)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prague_time: Option<u64>,
+}
+
+impl ChainOptions {
+    // Field-wise: values set here win over `fallback`
+    pub fn or(self, fallback: ChainOptions) -> ChainOptions {
+        ChainOptions {
+            genesis: self.genesis.or(fallback.genesis),
+            chain_id: self.chain_id.or(fallback.chain_id),
+            shanghai_time: self.shanghai_time.or(fallback.shanghai_time),
+            cancun_time: self.cancun_time.or(fallback.cancun_time),
+            prague_time: self.prague_time.or(fallback.prague_time),
+        }
+    }
+
+    pub fn genesis_file(&self) -> Result<Option<Vec<u8>>> {
+        match &self.genesis {
+            Some(path) => std::fs::read(path)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("Failed to read genesis {}: {}", path.display(), e)),
+            None => Ok(None),
+        }
+    }
+
+    #[cfg(feature = "with-reth")]
+    pub fn chain_spec(&self, base: Option<Genesis>) -> ChainSpec {
+        let mut genesis = base.unwrap_or_else(|| {
+            let mut genesis = Genesis::default();
+            genesis.config.chain_id = 777; // Custom chain ID
+            genesis
+        });
+
+        // Post-merge from genesis unless the genesis says otherwise
+        if genesis.config.terminal_total_difficulty.is_none() {
+            genesis.config.terminal_total_difficulty = Some(U256::ZERO);
+            genesis.config.terminal_total_difficulty_passed = true;
+        }
+
+        if let Some(chain_id) = self.chain_id {
+            genesis.config.chain_id = chain_id;
+        }
+        if let Some(ts) = self.shanghai_time {
+            genesis.config.shanghai_time = Some(ts);
+        }
+        if let Some(ts) = self.cancun_time {
+            genesis.config.cancun_time = Some(ts);
+        }
+        if let Some(ts) = self.prague_time {
+            genesis.config.prague_time = Some(ts);
+        }
+
+        ChainSpec::from(genesis)
+    }
+}
+
+// Proposer address -> fee recipient, from the genesis config's
+// `feeRecipients` (CometBFT validator address in hex -> EVM address). Being
+// part of the genesis, it is the same on every validator.
+#[cfg(feature = "with-reth")]
+pub fn fee_recipients(genesis: &Genesis) -> Result<HashMap<Vec<u8>, Address>> {
+    let Some(recipients) = genesis
+        .config
+        .extra_fields
+        .get_deserialized::<BTreeMap<String, Address>>("feeRecipients")
+    else {
+        return Ok(HashMap::new());
+    };
+    recipients
+        .map_err(|e| anyhow::anyhow!("Bad feeRecipients in genesis: {}", e))?
+        .into_iter()
+        .map(|(proposer, recipient)| {
+            let address = hex::decode(proposer.trim_start_matches("0x"))
+                .map_err(|e| anyhow::anyhow!("Bad validator address {}: {}", proposer, e))?;
+            if address.len() != 20 {
+                anyhow::bail!("Bad validator address {}: expected 20 bytes", proposer);
+            }
+            Ok((address, recipient))
+        })
+        .collect()
+}
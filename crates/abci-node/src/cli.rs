@@ -0,0 +1,159 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::chain::ChainOptions;
+use crate::config::{Config, LogFormat};
+
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// TOML config file (default: ./abci-node.toml if present)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the ABCI server
+    Start(StartArgs),
+    /// Write a default config file and create the data dir
+    Init(InitArgs),
+    /// Dump the committed state as a genesis JSON
+    Export(ExportArgs),
+    /// Manage state-sync snapshots
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+    /// Print version information
+    Version,
+}
+
+#[derive(Args)]
+pub struct StartArgs {
+    #[command(flatten)]
+    pub node: NodeArgs,
+
+    /// ABCI listen address
+    #[arg(long)]
+    pub listen_addr: Option<String>,
+
+    /// ABCI server read buffer size in bytes
+    #[arg(long)]
+    pub read_buf_size: Option<usize>,
+
+    /// Log output format
+    #[arg(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+
+    /// Serve Ethereum JSON-RPC on this address (enables [rpc])
+    #[arg(long)]
+    pub rpc_addr: Option<String>,
+
+    /// CometBFT RPC URL that eth_sendRawTransaction broadcasts to
+    #[arg(long)]
+    pub cometbft_url: Option<String>,
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    #[command(flatten)]
+    pub node: NodeArgs,
+
+    /// Overwrite an existing config file
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[command(flatten)]
+    pub node: NodeArgs,
+
+    /// Output file (default: stdout)
+    #[arg(long, short)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+pub enum SnapshotCommand {
+    /// List stored snapshots, newest first
+    List(SnapshotListArgs),
+    /// Write a stored snapshot to a single file
+    Export(SnapshotExportArgs),
+    /// Add an exported snapshot file to the store so peers can sync from it
+    Import(SnapshotImportArgs),
+}
+
+#[derive(Args)]
+pub struct SnapshotListArgs {
+    #[command(flatten)]
+    pub node: NodeArgs,
+}
+
+#[derive(Args)]
+pub struct SnapshotExportArgs {
+    #[command(flatten)]
+    pub node: NodeArgs,
+
+    /// Snapshot height (default: the newest)
+    #[arg(long)]
+    pub height: Option<i64>,
+
+    /// Output file
+    #[arg(long, short)]
+    pub out: PathBuf,
+}
+
+#[derive(Args)]
+pub struct SnapshotImportArgs {
+    #[command(flatten)]
+    pub node: NodeArgs,
+
+    /// File written by `snapshot export`
+    pub file: PathBuf,
+}
+
+// Flags shared by every command that opens the data dir
+#[derive(Args)]
+pub struct NodeArgs {
+    /// Data directory
+    #[arg(long)]
+    pub data_dir: Option<String>,
+
+    #[command(flatten)]
+    pub chain: ChainOptions,
+}
+
+impl NodeArgs {
+    pub fn apply(self, cfg: &mut Config) {
+        if let Some(data_dir) = self.data_dir {
+            cfg.data_dir = data_dir;
+        }
+        cfg.chain = self.chain.or(std::mem::take(&mut cfg.chain));
+    }
+}
+
+impl StartArgs {
+    pub fn apply(self, cfg: &mut Config) {
+        self.node.apply(cfg);
+        if let Some(addr) = self.listen_addr {
+            cfg.listen_addr = addr;
+        }
+        if let Some(size) = self.read_buf_size {
+            cfg.read_buf_size = size;
+        }
+        if let Some(format) = self.log_format {
+            cfg.log.format = format;
+        }
+        if let Some(addr) = self.rpc_addr {
+            cfg.rpc.enabled = true;
+            cfg.rpc.listen_addr = addr;
+        }
+        if let Some(url) = self.cometbft_url {
+            cfg.rpc.cometbft_url = url;
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::chain::ChainOptions;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./abci-node.toml";
+
+// Node configuration, read from TOML; every field has a default so a partial
+// file (or none at all) is fine. CLI flags override what's loaded here.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // ABCI listen address for CometBFT's proxy_app
+    pub listen_addr: String,
+    pub data_dir: String,
+    // ABCI server read buffer, in bytes
+    pub read_buf_size: usize,
+    pub chain: ChainOptions,
+    pub log: LogConfig,
+    pub rpc: RpcConfig,
+    pub snapshot: SnapshotConfig,
+    pub pruning: PruningConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: "127.0.0.1:26658".into(),
+            data_dir: "./data/reth".into(),
+            read_buf_size: 1024,
+            chain: ChainOptions::default(),
+            log: LogConfig::default(),
+            rpc: RpcConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            pruning: PruningConfig::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    // tracing EnvFilter directive; RUST_LOG takes precedence when set
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            filter: "info".into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcConfig {
+    pub enabled: bool,
+    pub listen_addr: String,
+    // CometBFT RPC that eth_sendRawTransaction forwards txs to
+    pub cometbft_url: String,
+    // Gas cap of eth_call and eth_estimateGas, on top of the block gas
+    // limit; 0 disables it
+    pub gas_cap: u64,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_addr: "127.0.0.1:8545".into(),
+            cometbft_url: "http://127.0.0.1:26657".into(),
+            gas_cap: 50_000_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotConfig {
+    // Blocks between state-sync snapshots; 0 disables them
+    pub interval: u64,
+    // Snapshots kept on disk, newest first, and at least 2; 0 keeps all
+    pub keep: usize,
+    // Bytes per chunk; CometBFT rejects chunks over 16 MB
+    pub chunk_size: usize,
+    // Payload encoding of new snapshots: 1 = JSON, 2 = gzip-compressed JSON
+    pub format: u32,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            interval: 1000,
+            keep: 2,
+            chunk_size: 4 << 20,
+            format: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PruningConfig {
+    pub mode: PruningMode,
+    // Recent blocks kept by `recent` and `snapshot`, including the latest
+    pub keep_recent: u64,
+}
+
+impl Default for PruningConfig {
+    fn default() -> Self {
+        Self {
+            mode: PruningMode::Archive,
+            keep_recent: 100_000,
+        }
+    }
+}
+
+// What commit tells CometBFT to keep (retain_height) and what the app keeps
+// of its own history (blocks, receipts, state, tx and hash indexes)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PruningMode {
+    // Keep everything
+    Archive,
+    // Keep the last `keep_recent` blocks
+    Recent,
+    // Like `recent`, but never drop blocks at or above the oldest stored
+    // snapshot, so peers restoring it can still fetch what follows
+    Snapshot,
+}
+
+impl Config {
+    // A missing file at the default path means defaults; an explicitly
+    // requested file has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(p) => (p, true),
+            None => (Path::new(DEFAULT_CONFIG_PATH), false),
+        };
+
+        match std::fs::read_to_string(path) {
+            Ok(raw) => toml::from_str(&raw)
+                .with_context(|| format!("Invalid config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => Ok(Self::default()),
+            Err(e) => Err(anyhow::anyhow!("Failed to read config {}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, toml::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -1,58 +1,568 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use tendermint::block::Header as TmHeader;
 use tendermint_proto::abci::Event as AbciEvent;
 
+use crate::chain::ChainOptions;
+#[cfg(feature = "with-reth")]
+use crate::chain::fee_recipients;
+use crate::config::{PruningConfig, PruningMode};
+use crate::store::{AppStore, BlockMeta, BlockParams};
+use crate::wire::apphash_from;
+
 #[cfg(feature = "with-reth")]
 use {
     reth_db::{
         mdbx::DatabaseArguments,
     },
     reth_primitives::{
+        proofs::calculate_receipt_root,
+        Receipt as EthReceipt,
         TransactionSigned,
+        TxType,
     },
     reth_transaction_pool::{
         TransactionPool,
     },
     reth::{
-        primitives::{Address, Bytes, B256, U256},
-        chainspec::ChainSpec,
+        primitives::{keccak256, Address, Bytes, TxKind, B256, U256},
+        chainspec::{ChainSpec, EthereumHardforks, EthChainSpec, Genesis},
+        revm::{
+            db::CacheDB,
+            primitives::{BlockEnv, Bytecode, EVMError, ExecutionResult, Output, SpecId, TxEnv},
+            Database, Evm,
+        },
     },
-    std::sync::Arc,
+    reth_trie_common::EMPTY_ROOT_HASH,
+    std::collections::HashMap,
+    std::sync::Mutex,
+    tracing::info,
+    crate::mempool::PendingPool,
+    crate::config::SnapshotConfig,
+    crate::snapshot::{SnapshotManager, SnapshotPayload},
+    crate::state::{RecentHashes, StateDiff, StateView, WorldState, WorldView},
+    crate::store::STATE_CHECKPOINT_INTERVAL,
 };
 
+// Base fee of the first block unless the genesis sets one, in wei
+pub const INITIAL_BASE_FEE: u64 = 1_000_000_000;
+
+// EIP-1559 parameters (mainnet values)
+const BASE_FEE_MAX_CHANGE_DENOMINATOR: u64 = 8;
+const ELASTICITY_MULTIPLIER: u64 = 2;
+
+// EVM gas limit when CometBFT's max_gas is unlimited (-1)
+pub const DEFAULT_BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+// How many past block hashes BLOCKHASH can read
+pub const BLOCKHASH_WINDOW: u64 = 256;
+
+// Why executing or committing a block failed. A `Tx` failure only concerns
+// that tx, which is rejected in its DeliverTx result. A `State` failure means
+// the node's state can no longer be trusted to match the rest of the
+// network, so it must stop instead of answering with a stale app hash.
+#[derive(Debug)]
+pub enum ExecError {
+    Tx(anyhow::Error),
+    State(anyhow::Error),
+}
+
+impl ExecError {
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ExecError::State(_))
+    }
+}
+
+impl std::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecError::Tx(e) => write!(f, "{:#}", e),
+            ExecError::State(e) => write!(f, "state failure: {:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+// eth_estimateGas: the call reverts even at the gas cap. Carries the revert
+// data, which the RPC returns like eth_call does.
+#[cfg(feature = "with-reth")]
+#[derive(Debug)]
+pub struct Reverted {
+    pub gas_limit: u64,
+    pub output: Vec<u8>,
+}
+
+#[cfg(feature = "with-reth")]
+impl std::fmt::Display for Reverted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Execution reverted with gas limit {}: {}", self.gas_limit, hex0x(&self.output))
+    }
+}
+
+#[cfg(feature = "with-reth")]
+impl std::error::Error for Reverted {}
+
 #[derive(Clone)]
 pub struct RethCtx {
     pub db_path: String,
+    pub store: AppStore,
+    pub chain_opts: ChainOptions,
+
+    // Base fee for the next block, set from the last commit
+    base_fee: Arc<AtomicU64>,
+
+    // Consensus block params in force; max_gas is the EVM block gas limit
+    block_params: Arc<RwLock<BlockParams>>,
+
+    pruning: PruningConfig,
     
     #[cfg(feature = "with-reth")]
     pub chain_spec: Arc<ChainSpec>,
+
+    // Last committed EVM state; blocks execute against an overlay on top of it
+    #[cfg(feature = "with-reth")]
+    pub state: Arc<RwLock<WorldState>>,
+
+    // Hashes of the last committed blocks, for BLOCKHASH
+    #[cfg(feature = "with-reth")]
+    pub block_hashes: Arc<RwLock<RecentHashes>>,
+
+    // Mempool txs admitted since the last commit, layered on `state` by CheckTx
+    #[cfg(feature = "with-reth")]
+    pub pending: Arc<Mutex<PendingPool>>,
+
+    // CometBFT proposer address -> EVM coinbase, from the genesis
+    #[cfg(feature = "with-reth")]
+    fee_recipients: Arc<HashMap<Vec<u8>, Address>>,
+
+    // Hash of the effective genesis, part of every app hash
+    #[cfg(feature = "with-reth")]
+    genesis_hash: B256,
+
+    #[cfg(feature = "with-reth")]
+    pub snapshots: SnapshotManager,
 }
 
 impl RethCtx {
-    pub fn open(path: &str) -> Result<Self> {
+    pub fn open(path: &str, chain_opts: ChainOptions) -> Result<Self> {
         #[cfg(feature = "with-reth")]
         {
             // Create database directory if it doesn't exist
             std::fs::create_dir_all(path)?;
+            let store = AppStore::open(path)?;
             
-            // Create a simple chain spec
-            let chain_spec = Arc::new(
-                ChainSpec::builder()
-                    .chain(777u64) // Custom chain ID
-                    .paris_activated() // Post-merge
-                    .build()
+            let chain_spec = Arc::new(load_chain_spec(&chain_opts, store.genesis()?)?);
+            info!(
+                "Chain id {}, shanghai: {:?}, cancun: {:?}, prague: {:?}",
+                chain_spec.chain().id(),
+                chain_spec.genesis().config.shanghai_time,
+                chain_spec.genesis().config.cancun_time,
+                chain_spec.genesis().config.prague_time,
             );
             
+            // Resume from the last committed world state, if any
+            let state = match store.latest()? {
+                Some(meta) => store.state(meta.height)?.ok_or_else(|| {
+                    anyhow::anyhow!("Missing world state for committed height {}", meta.height)
+                })?,
+                None => store.state(0)?.unwrap_or_default(),
+            };
+
+            let block_hashes = load_block_hashes(&store, store.latest()?.map_or(0, |m| m.height))?;
+            store.reindex_txs(store.latest()?.map_or(0, |m| m.height))?;
+            let fee_recipients = fee_recipients(chain_spec.genesis())?;
+            let genesis_hash = genesis_hash(&chain_opts, store.genesis()?)?;
+            let block_params = match store.latest()?.and_then(|meta| meta.block_params) {
+                Some(params) => params,
+                None => store.params()?.unwrap_or_default(),
+            };
+            let base_fee = match store.latest()? {
+                Some(meta) => meta.next_base_fee,
+                None => genesis_base_fee(chain_spec.genesis())?,
+            };
+
             Ok(Self {
                 db_path: path.into(),
+                store,
+                chain_opts,
+                base_fee: Arc::new(AtomicU64::new(base_fee)),
+                block_params: Arc::new(RwLock::new(block_params)),
+                pruning: PruningConfig::default(),
                 chain_spec,
+                state: Arc::new(RwLock::new(state)),
+                block_hashes: Arc::new(RwLock::new(block_hashes)),
+                pending: Arc::new(Mutex::new(PendingPool::default())),
+                fee_recipients: Arc::new(fee_recipients),
+                genesis_hash,
+                snapshots: SnapshotManager::open(path, SnapshotConfig::default())?,
             })
         }
         
         #[cfg(not(feature = "with-reth"))]
         {
-            Ok(Self { db_path: path.into() })
+            let store = AppStore::open(path)?;
+            store.reindex_txs(store.latest()?.map_or(0, |m| m.height))?;
+            let base_fee = store
+                .latest()?
+                .map_or(INITIAL_BASE_FEE, |meta| meta.next_base_fee);
+            let block_params = match store.latest()?.and_then(|meta| meta.block_params) {
+                Some(params) => params,
+                None => store.params()?.unwrap_or_default(),
+            };
+            Ok(Self {
+                db_path: path.into(),
+                store,
+                chain_opts,
+                base_fee: Arc::new(AtomicU64::new(base_fee)),
+                block_params: Arc::new(RwLock::new(block_params)),
+                pruning: PruningConfig::default(),
+            })
+        }
+    }
+
+    // Seed state from InitChain's app_state_bytes (an Ethereum genesis JSON),
+    // falling back to --genesis, and return the genesis app hash. With
+    // neither, the default chain starts from empty state.
+    pub fn init_genesis(&mut self, app_state: &[u8]) -> Result<Option<[u8; 32]>> {
+        #[cfg(feature = "with-reth")]
+        {
+            let raw = if app_state.is_empty() {
+                match self.chain_opts.genesis_file()? {
+                    Some(raw) => raw,
+                    None => return Ok(None),
+                }
+            } else {
+                app_state.to_vec()
+            };
+
+            let chain_spec = Arc::new(load_chain_spec(&self.chain_opts, Some(raw.clone()))?);
+            let base_fee = genesis_base_fee(chain_spec.genesis())?;
+            let recipients = fee_recipients(chain_spec.genesis())?;
+            let world = WorldState::from_genesis(chain_spec.genesis());
+
+            // Keep the raw genesis so the chain spec survives restarts, and
+            // its state as height 0 for historical reads
+            self.store.put_genesis(&raw)?;
+            self.store.put_state(0, &world)?;
+            self.genesis_hash = genesis_hash(&self.chain_opts, Some(raw.clone()))?;
+            self.chain_spec = chain_spec;
+            self.base_fee.store(base_fee, Ordering::SeqCst);
+            self.fee_recipients = Arc::new(recipients);
+            let context = self.genesis_context()?;
+            let app_hash = apphash_from(world.state_root().0, EMPTY_ROOT_HASH.0, context);
+            *self.state.write().unwrap() = world;
+
+            Ok(Some(app_hash))
+        }
+
+        #[cfg(not(feature = "with-reth"))]
+        {
+            let _ = app_state;
+            Ok(None)
+        }
+    }
+
+    // Current committed state as a genesis JSON that a new chain can start from
+    pub fn export_genesis(&self) -> Result<Vec<u8>> {
+        #[cfg(feature = "with-reth")]
+        {
+            let mut genesis = self.chain_spec.genesis().clone();
+            genesis.alloc = self.state.read().unwrap().to_genesis_alloc();
+            if let Some(meta) = self.store.latest()? {
+                genesis.timestamp = meta.timestamp;
+            }
+            Ok(serde_json::to_vec_pretty(&genesis)?)
+        }
+
+        #[cfg(not(feature = "with-reth"))]
+        Err(anyhow::anyhow!("State export requires the with-reth feature"))
+    }
+
+    #[cfg(feature = "with-reth")]
+    pub fn with_snapshot_config(mut self, config: SnapshotConfig) -> Result<Self> {
+        self.snapshots = SnapshotManager::open(&self.db_path, config)?;
+        Ok(self)
+    }
+
+    // Snapshot the state just committed at `meta.height`, if one is due.
+    // Only the copy happens here; encoding and writing are in the background.
+    #[cfg(feature = "with-reth")]
+    pub fn maybe_snapshot(&self, meta: &BlockMeta) -> Result<()> {
+        if !self.snapshots.due(meta.height) {
+            return Ok(());
+        }
+        self.snapshots.spawn(self.snapshot_payload(meta)?);
+        Ok(())
+    }
+
+    // The state just committed at `meta.height`, with what a node restoring
+    // it needs besides
+    #[cfg(feature = "with-reth")]
+    pub fn snapshot_payload(&self, meta: &BlockMeta) -> Result<SnapshotPayload> {
+        Ok(SnapshotPayload {
+            meta: meta.clone(),
+            params: self.block_params(),
+            genesis: self.store.genesis()?,
+            state: self.state.read().unwrap().clone(),
+            block_hashes: self.block_hashes.read().unwrap().clone(),
+        })
+    }
+
+    // Install a verified state-sync snapshot as the committed state and
+    // reload from it
+    #[cfg(feature = "with-reth")]
+    pub fn restore_snapshot(&mut self, payload: SnapshotPayload) -> Result<()> {
+        if let Some(genesis) = &payload.genesis {
+            self.store.put_genesis(genesis)?;
+        }
+        self.store.put_params(&payload.params)?;
+        self.store.put_state(payload.meta.height, &payload.state)?;
+        self.store.put_restored_hashes(&payload.block_hashes)?;
+        self.store.put_block_hash(&payload.meta.hash, payload.meta.height)?;
+        self.store.put_block(&payload.meta)?;
+        // Nothing below the snapshot height was ever stored here
+        self.store.put_base_height(payload.meta.height)?;
+        let snapshots = self.snapshots.clone();
+        let pruning = self.pruning.clone();
+        *self = RethCtx::open(&self.db_path, self.chain_opts.clone())?;
+        self.snapshots = snapshots;
+        self.pruning = pruning;
+        Ok(())
+    }
+
+    pub fn with_pruning_config(mut self, config: PruningConfig) -> Self {
+        self.pruning = config;
+        self
+    }
+
+    // Oldest height to keep once `height` is committed, per the [pruning]
+    // policy; 0 keeps everything. The blocks in the BLOCKHASH window are
+    // always kept, as a restart reloads it from them.
+    pub fn retain_height(&self, height: i64) -> Result<i64> {
+        let keep = self.pruning.keep_recent.max(BLOCKHASH_WINDOW + 1);
+        let recent = (height - keep as i64 + 1).max(0);
+        match self.pruning.mode {
+            PruningMode::Archive => Ok(0),
+            PruningMode::Recent => Ok(recent),
+            PruningMode::Snapshot => Ok(self.oldest_snapshot()?.map_or(0, |h| h.min(recent))),
+        }
+    }
+
+    #[cfg(feature = "with-reth")]
+    fn oldest_snapshot(&self) -> Result<Option<i64>> {
+        Ok(self.snapshots.store.list()?.last().map(|info| info.height))
+    }
+
+    #[cfg(not(feature = "with-reth"))]
+    fn oldest_snapshot(&self) -> Result<Option<i64>> {
+        Ok(None)
+    }
+
+    // Apply the pruning policy after committing `height`: drop the app's
+    // own history below the retain height and return it for CometBFT
+    pub fn prune(&self, height: i64) -> Result<i64> {
+        let retain = self.retain_height(height)?;
+        if retain > 0 {
+            let pruned = self.store.prune_below(retain)?;
+            if pruned > 0 {
+                tracing::debug!("Pruned {} heights below {}", pruned, retain);
+            }
+        }
+        Ok(retain)
+    }
+
+    // Height of the last committed block; 0 before the first commit. It
+    // moves together with the live state, once the block is on disk.
+    pub fn latest_height(&self) -> Result<i64> {
+        #[cfg(feature = "with-reth")]
+        return Ok(self.state.read().unwrap().height);
+
+        #[cfg(not(feature = "with-reth"))]
+        Ok(self.store.latest()?.map_or(0, |meta| meta.height))
+    }
+
+    // Context hash of the genesis app hash: no block yet, the genesis base
+    // fee and InitChain's block params
+    #[cfg(feature = "with-reth")]
+    fn genesis_context(&self) -> Result<[u8; 32]> {
+        let meta = BlockMeta {
+            next_base_fee: genesis_base_fee(self.chain_spec.genesis())?,
+            block_params: Some(self.store.params()?.unwrap_or_default()),
+            ..Default::default()
+        };
+        Ok(context_hash(self.genesis_hash, &meta, &RecentHashes::default()))
+    }
+
+    // Receipts root and context hash that the app hash at `height` commits
+    // to along with the state root
+    #[cfg(feature = "with-reth")]
+    pub fn app_hash_parts(&self, height: i64) -> Result<([u8; 32], [u8; 32])> {
+        match self.store.block(height)? {
+            Some(meta) => Ok((meta.receipts_root, meta.context_hash)),
+            None => Ok((EMPTY_ROOT_HASH.0, self.genesis_context()?)),
+        }
+    }
+
+    // Run `f` on the world state as committed at `height`. For the latest
+    // height that is the live state, read-locked while `f` runs so a commit
+    // can't move it on underneath; older heights are rebuilt from the store.
+    #[cfg(feature = "with-reth")]
+    pub fn with_state_at<T>(&self, height: i64, f: impl FnOnce(&WorldState) -> Result<T>) -> Result<T> {
+        {
+            let live = self.state.read().unwrap();
+            if height > live.height {
+                return Err(anyhow::anyhow!(
+                    "Height {} is not committed yet (latest {})",
+                    height,
+                    live.height
+                ));
+            }
+            if height == live.height {
+                return f(&live);
+            }
+        }
+        let base = self.store.base_height()?;
+        if height < base {
+            return Err(anyhow::anyhow!("State at height {} has been pruned (oldest {})", height, base));
+        }
+        let state = self
+            .store
+            .state(height)?
+            .ok_or_else(|| anyhow::anyhow!("No state stored for height {}", height))?;
+        f(&state)
+    }
+
+    // Fold an executed block into the committed state and persist it. Any
+    // error leaves memory and disk disagreeing with the network, so it is a
+    // state failure; the caller must halt.
+    pub fn commit_block(&self, exec: BlockExec, block_params: BlockParams) -> Result<BlockMeta, ExecError> {
+        let receipts = exec.receipts().to_vec();
+        let mut meta = BlockMeta {
+            height: exec.height(),
+            hash: exec.hash(),
+            parent_hash: exec.parent_hash(),
+            base_fee: exec.base_fee(),
+            next_base_fee: exec.next_base_fee(),
+            gas_limit: exec.gas_limit(),
+            coinbase: exec.coinbase(),
+            block_params: Some(block_params),
+            tx_hashes: exec.tx_hashes(),
+            ..Default::default()
+        };
+
+        // The window as the next block will see it, this block included
+        #[cfg(feature = "with-reth")]
+        {
+            let mut block_hashes = self.block_hashes.read().unwrap().clone();
+            block_hashes.insert(meta.height as u64, B256::from(meta.hash));
+            meta.context_hash = context_hash(self.genesis_hash, &meta, &block_hashes);
+        }
+
+        // The state stays write-locked until the block is on disk, so readers
+        // see the live state, its height and the store move together
+        #[cfg(feature = "with-reth")]
+        let mut world = self.state.write().unwrap();
+        #[cfg(feature = "with-reth")]
+        let committed = exec.commit(&mut world)?;
+        #[cfg(not(feature = "with-reth"))]
+        let committed = exec.commit()?;
+
+        meta.app_hash = apphash_from(committed.state_root, committed.receipts_root, meta.context_hash);
+        meta.state_root = committed.state_root;
+        meta.receipts_root = committed.receipts_root;
+        meta.gas_used = committed.gas_used;
+        meta.timestamp = committed.timestamp;
+
+        #[cfg(feature = "with-reth")]
+        let persisted = self.persist_block(&meta, &receipts, &committed.diff, &world);
+        #[cfg(not(feature = "with-reth"))]
+        let persisted = self.persist_block(&meta, &receipts);
+        persisted.map_err(|e| {
+            ExecError::State(e.context(format!("Failed to persist block {}", meta.height)))
+        })?;
+
+        #[cfg(feature = "with-reth")]
+        {
+            world.height = meta.height;
+        }
+        Ok(meta)
+    }
+
+    // Durably record a committed block: its state diff, plus the full state
+    // at checkpoint heights. The block pointer is written last.
+    fn persist_block(
+        &self,
+        meta: &BlockMeta,
+        receipts: &[Receipt],
+        #[cfg(feature = "with-reth")] diff: &StateDiff,
+        #[cfg(feature = "with-reth")] world: &WorldState,
+    ) -> Result<()> {
+        #[cfg(feature = "with-reth")]
+        {
+            self.store.put_diff(meta.height, diff)?;
+            if meta.height % STATE_CHECKPOINT_INTERVAL == 0 {
+                self.store.put_state(meta.height, world)?;
+            }
+        }
+
+        self.store.put_receipts(meta.height, receipts)?;
+        self.store.put_tx_locations(meta)?;
+        self.store.put_block_hash(&meta.hash, meta.height)?;
+        self.store.put_block(meta)?;
+        #[cfg(feature = "with-reth")]
+        self.block_hashes
+            .write()
+            .unwrap()
+            .insert(meta.height as u64, B256::from(meta.hash));
+        self.base_fee.store(meta.next_base_fee, Ordering::SeqCst);
+        if let Some(params) = meta.block_params {
+            *self.block_params.write().unwrap() = params;
+        }
+        Ok(())
+    }
+
+    pub fn base_fee(&self) -> u64 {
+        self.base_fee.load(Ordering::SeqCst)
+    }
+
+    pub fn block_params(&self) -> BlockParams {
+        *self.block_params.read().unwrap()
+    }
+
+    // Record params CometBFT will enforce from the next block on
+    pub fn set_block_params(&self, params: BlockParams) -> Result<()> {
+        self.store.put_params(&params)?;
+        *self.block_params.write().unwrap() = params;
+        Ok(())
+    }
+
+    pub fn block_gas_limit(&self) -> u64 {
+        match self.block_params().max_gas {
+            max_gas if max_gas > 0 => max_gas as u64,
+            _ => DEFAULT_BLOCK_GAS_LIMIT,
+        }
+    }
+
+    // Params EndBlock should hand to CometBFT: the genesis gasLimit, but only
+    // while InitChain left max_gas unlimited. A max_gas the operator set in
+    // CometBFT's genesis is kept. The genesis is the same on every validator,
+    // so they all propose the same update.
+    pub fn block_params_update(&self) -> Option<BlockParams> {
+        #[cfg(feature = "with-reth")]
+        {
+            let target = i64::try_from(self.chain_spec.genesis().gas_limit)
+                .ok()
+                .filter(|limit| *limit > 0)?;
+            let current = self.block_params();
+            (current.max_gas <= 0).then_some(BlockParams { max_gas: target, ..current })
         }
+
+        #[cfg(not(feature = "with-reth"))]
+        None
     }
 
     pub fn validate_tx_basic(&self, tx: &crate::wire::TxEnvelopeAny) -> Result<()> {
@@ -62,7 +572,19 @@ impl RethCtx {
             if tx.gas_limit() == 0 {
                 return Err(anyhow::anyhow!("Gas limit cannot be zero"));
             }
+            check_tx_type(tx)?;
             
+            // Replay protection: EIP-155 txs must target this chain
+            if let Some(chain_id) = tx.chain_id() {
+                if chain_id != self.chain_spec.chain().id() {
+                    return Err(anyhow::anyhow!(
+                        "Wrong chain id {}, expected {}",
+                        chain_id,
+                        self.chain_spec.chain().id()
+                    ));
+                }
+            }
+
             // Verify signature
             tx.recover_signer()
                 .map_err(|e| anyhow::anyhow!("Invalid signature: {}", e))?;
@@ -74,198 +596,1506 @@ impl RethCtx {
         Ok(())
     }
 
-    pub fn propose_block(&self, _max_bytes: usize) -> Proposed {
-        // For now, return empty block
-        Proposed { txs: vec![] }
+    // Full CheckTx: stateless checks, then fee, nonce and balance against the
+    // last committed state plus the sender's pending txs
+    pub fn check_tx(&self, tx: &crate::wire::TxEnvelopeAny) -> Result<CheckedTx> {
+        self.validate_tx_basic(tx)?;
+
+        #[cfg(feature = "with-reth")]
+        {
+            let sender = crate::wire::get_tx_sender(tx)?;
+            let checked = self.check_tx_state(tx, sender, false)?;
+            self.pending
+                .lock()
+                .unwrap()
+                .remember_sender(tx.hash(), sender);
+            Ok(checked)
+        }
+
+        #[cfg(not(feature = "with-reth"))]
+        Ok(CheckedTx {
+            sender: String::new(),
+            nonce: 0,
+            gas_wanted: 100_000,
+            priority: 0,
+        })
     }
 
-    pub fn quick_validate_proposal(&self, txs: &[Vec<u8>]) -> bool {
+    // ReCheck after a commit: signature and chain id were verified when the
+    // tx first entered the mempool, so only re-run the state-dependent checks,
+    // and only evict what the commit made unincludable
+    pub fn recheck_tx(&self, tx: &crate::wire::TxEnvelopeAny) -> Result<CheckedTx> {
         #[cfg(feature = "with-reth")]
         {
-            // Quick stateless validation
-            for tx_bytes in txs {
-                // Try to decode each transaction
-                if crate::wire::decode_eth_tx(tx_bytes).is_err() {
-                    return false;
+            let cached = self.pending.lock().unwrap().cached_sender(&tx.hash());
+            match cached {
+                Some(sender) => self.check_tx_state(tx, sender, true),
+                None => {
+                    self.validate_tx_basic(tx)?;
+                    let sender = crate::wire::get_tx_sender(tx)?;
+                    let checked = self.check_tx_state(tx, sender, true)?;
+                    self.pending.lock().unwrap().remember_sender(tx.hash(), sender);
+                    Ok(checked)
                 }
             }
-            true
         }
-        
+
         #[cfg(not(feature = "with-reth"))]
-        true
+        self.check_tx(tx)
     }
-}
 
-pub struct Proposed {
-    pub txs: Vec<Vec<u8>>,
-}
+    // A rechecked tx may wait out a base fee above its max fee, and its
+    // sender's txs may be rechecked out of nonce order
+    #[cfg(feature = "with-reth")]
+    fn check_tx_state(
+        &self,
+        tx: &crate::wire::TxEnvelopeAny,
+        sender: Address,
+        recheck: bool,
+    ) -> Result<CheckedTx> {
+        let base_fee = self.base_fee();
+        let max_fee = tx.max_fee_per_gas();
+        if max_fee < base_fee as u128 && !recheck {
+            return Err(anyhow::anyhow!(
+                "Max fee per gas {} below base fee {}",
+                max_fee,
+                base_fee
+            ));
+        }
 
-pub struct BlockExec {
-    header: TmHeader,
-    receipts: Vec<Receipt>,
-    gas_used: u64,
-    state_root: [u8; 32],
-    
+        let cost = U256::from(tx.gas_limit()) * U256::from(max_fee) + tx.value();
+        let state = self.state.read().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        if recheck {
+            pending.readmit(&state, sender, tx.nonce(), cost)?;
+        } else {
+            pending.admit(&state, sender, tx.nonce(), cost)?;
+        }
+
+        // Priority is what the proposer earns per gas at the current base fee
+        let tip = tx.effective_tip_per_gas(Some(base_fee)).unwrap_or_default();
+        Ok(CheckedTx {
+            sender: sender.to_string(),
+            nonce: tx.nonce(),
+            gas_wanted: tx.gas_limit(),
+            priority: i64::try_from(tip).unwrap_or(i64::MAX),
+        })
+    }
+
+    // Committed state moved on; CometBFT rechecks what's left in its mempool
+    pub fn reset_pending(&self) {
+        #[cfg(feature = "with-reth")]
+        self.pending.lock().unwrap().reset();
+    }
+
+    // Coinbase for blocks from `proposer`, per the genesis feeRecipients. A
+    // CometBFT address is no EVM account anyone holds the key to, so blocks
+    // from unlisted validators credit the zero address: their priority fees
+    // are burned like the base fee.
     #[cfg(feature = "with-reth")]
-    executed_txs: Vec<TransactionSigned>,
-}
+    pub fn fee_recipient(&self, proposer: &[u8]) -> Address {
+        self.fee_recipients.get(proposer).copied().unwrap_or(Address::ZERO)
+    }
 
-impl BlockExec {
-    pub fn new(_reth: &RethCtx, header: TmHeader) -> Self {
-        Self {
-            header,
-            receipts: vec![],
-            gas_used: 0,
-            state_root: [0u8; 32],
-            #[cfg(feature = "with-reth")]
-            executed_txs: vec![],
+    #[cfg(feature = "with-reth")]
+    pub fn spec_id_at(&self, timestamp: u64) -> SpecId {
+        if self.chain_spec.is_prague_active_at_timestamp(timestamp) {
+            SpecId::PRAGUE
+        } else if self.chain_spec.is_cancun_active_at_timestamp(timestamp) {
+            SpecId::CANCUN
+        } else if self.chain_spec.is_shanghai_active_at_timestamp(timestamp) {
+            SpecId::SHANGHAI
+        } else {
+            SpecId::MERGE
         }
     }
 
-    pub fn apply_tx(&mut self, reth: &RethCtx, tx: crate::wire::TxEnvelopeAny) -> Result<Receipt> {
+    // Build a proposal from CometBFT's mempool txs: highest effective tip
+    // first, each sender's txs in nonce order, within max_bytes and the block
+    // gas limit. Every pick is executed against a scratch overlay, and a tx
+    // that fails takes the rest of its sender's txs with it (nonce gap).
+    pub fn propose_block(&self, txs: &[Vec<u8>], max_bytes: usize, block: BlockInfo) -> Proposed {
         #[cfg(feature = "with-reth")]
         {
-            use reth_evm::execute::{BlockEnv, TxEnv};
-            
-            // Create block environment from CometBFT header
-            let block_env = BlockEnv {
-                number: U256::from(self.header.height.value()),
-                coinbase: Address::ZERO, // Set to validator/proposer address
-                timestamp: U256::from(self.header.time.unix_timestamp()),
-                gas_limit: U256::from(30_000_000u64), // Configure as needed
-                basefee: U256::from(1_000_000_000u64), // 1 gwei, configure as needed
-                difficulty: U256::ZERO, // Post-merge
-                prevrandao: Some(B256::ZERO), // Should use proper randomness
-                blob_excess_gas_and_price: None,
-            };
-            
-            // Create transaction environment
-            let caller = tx.recover_signer()?;
-            let tx_env = TxEnv {
-                caller,
-                gas_limit: tx.gas_limit(),
-                gas_price: U256::from(tx.max_fee_per_gas().unwrap_or(1_000_000_000)),
-                transact_to: tx.to().copied(),
-                value: tx.value(),
-                data: tx.input().clone(),
-                nonce: Some(tx.nonce()),
-                chain_id: Some(reth.chain_spec.chain().id()),
-                access_list: tx.access_list().cloned().unwrap_or_default().0,
-                gas_priority_fee: tx.max_priority_fee_per_gas().map(U256::from),
-                blob_hashes: vec![],
-                max_fee_per_blob_gas: None,
-                authorization_list: None,
-            };
-            
-            // For now, create a simple receipt without full EVM execution
-            // Full EVM integration would require revm setup
-            let receipt = Receipt {
-                success: true,
-                gas_used: 21000, // Basic transfer gas
-                logs: vec![],
-            };
-            
-            self.receipts.push(receipt.clone());
-            self.gas_used += receipt.gas_used;
-            self.executed_txs.push(tx.into_signed());
-            
-            Ok(receipt)
+            use std::cmp::Reverse;
+            use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+            let mut by_sender: HashMap<Address, Vec<(usize, TransactionSigned)>> = HashMap::new();
+            for (idx, raw) in txs.iter().enumerate() {
+                let Ok(tx) = crate::wire::decode_eth_tx(raw) else { continue };
+                let Ok(sender) = crate::wire::get_tx_sender(&tx) else { continue };
+                by_sender.entry(sender).or_default().push((idx, tx));
+            }
+
+            let mut queues: HashMap<Address, VecDeque<(usize, TransactionSigned)>> = by_sender
+                .into_iter()
+                .map(|(sender, mut txs)| {
+                    txs.sort_by_key(|(_, tx)| tx.nonce());
+                    (sender, txs.into())
+                })
+                .collect();
+
+            // (tip, earliest mempool position wins ties, sender)
+            type Heads = BinaryHeap<(u128, Reverse<usize>, Address)>;
+            fn push_head(heads: &mut Heads, base_fee: u64, sender: Address, queue: &VecDeque<(usize, TransactionSigned)>) {
+                if let Some((idx, tx)) = queue.front() {
+                    // Can't pay the base fee: never includable
+                    if let Some(tip) = tx.effective_tip_per_gas(Some(base_fee)) {
+                        heads.push((tip, Reverse(*idx), sender));
+                    }
+                }
+            }
+
+            let base_fee = self.base_fee();
+            let mut heads = Heads::new();
+            for (sender, queue) in &queues {
+                push_head(&mut heads, base_fee, *sender, queue);
+            }
+
+            let mut sim = BlockExec::new(self, block);
+            let mut out = Vec::new();
+            let mut bytes = 0usize;
+            while let Some((_, _, sender)) = heads.pop() {
+                let queue = queues.get_mut(&sender).expect("queued sender");
+                let (idx, tx) = queue.pop_front().expect("non-empty queue");
+                let raw = &txs[idx];
+
+                let size = proto_tx_size(raw.len());
+                if bytes + size > max_bytes {
+                    continue;
+                }
+                // Also refuses txs that would overflow the block gas limit
+                if sim.apply_tx(self, tx).is_err() {
+                    continue;
+                }
+
+                bytes += size;
+                out.push(raw.clone());
+                push_head(&mut heads, base_fee, sender, queue);
+            }
+
+            info!("Proposing {} of {} mempool txs, gas {}", out.len(), txs.len(), sim.gas_used);
+            Proposed { txs: out }
         }
-        
+
         #[cfg(not(feature = "with-reth"))]
         {
-            let _ = tx;
-            Ok(Receipt::ok())
+            // No fee data without reth: keep CometBFT's order, trim to size
+            let _ = block;
+            let mut bytes = 0usize;
+            let txs = txs
+                .iter()
+                .take_while(|raw| {
+                    bytes += proto_tx_size(raw.len());
+                    bytes <= max_bytes
+                })
+                .cloned()
+                .collect();
+            Proposed { txs }
         }
     }
 
-    pub fn commit(self) -> Result<([u8; 32], [u8; 32], u64, u64)> {
+    // Execute a proposed block against the committed state and reject it if
+    // any tx is undecodable, duplicated, underpays the base fee, or fails in
+    // apply_tx (block gas limit, bad signature, nonce, or funds). The
+    // executed block is returned for reuse.
+    pub fn validate_proposal(&self, txs: &[Vec<u8>], block: BlockInfo) -> Result<BlockExec> {
         #[cfg(feature = "with-reth")]
         {
-            // In a real implementation, you would:
-            // 1. Apply state changes to the database
-            // 2. Calculate the state root from the trie
-            // 3. Calculate the receipts root
-            // 4. Persist everything
-            
-            // For now, return placeholder values
-            let state_root = [1u8; 32]; // Should be actual state root
-            let receipts_root = [2u8; 32]; // Should be actual receipts root
-            let timestamp = self.header.time.unix_timestamp() as u64;
-            
-            Ok((state_root, receipts_root, self.gas_used, timestamp))
-        }
-        
+            let base_fee = self.base_fee();
+            let mut seen = std::collections::HashSet::new();
+            let mut sim = BlockExec::new(self, block);
+            for (i, raw) in txs.iter().enumerate() {
+                let tx = crate::wire::decode_eth_tx(raw)
+                    .map_err(|e| anyhow::anyhow!("tx {}: {}", i, e))?;
+                if !seen.insert(tx.hash()) {
+                    return Err(anyhow::anyhow!("tx {}: duplicate of {}", i, tx.hash()));
+                }
+                if tx.max_fee_per_gas() < base_fee as u128 {
+                    return Err(anyhow::anyhow!("tx {}: max fee below base fee {}", i, base_fee));
+                }
+                sim.apply_tx(self, tx)
+                    .map_err(|e| anyhow::anyhow!("tx {}: {}", i, e))?;
+            }
+            Ok(sim.rewind())
+        }
+        
         #[cfg(not(feature = "with-reth"))]
         {
-            let state_root = [0u8; 32];
-            let receipts_root = [0u8; 32];
-            let ts = 0u64;
-            Ok((state_root, receipts_root, self.gas_used, ts))
+            let _ = txs;
+            Ok(BlockExec::new(self, block))
+        }
+    }
+
+    // Run a call against the state committed at `height`, on a throwaway
+    // overlay. Nonce and base fee are not checked, as in eth_call.
+    #[cfg(feature = "with-reth")]
+    pub fn call(&self, request: &CallRequest, height: i64, overrides: &StateOverrides) -> Result<Receipt> {
+        let block_env = self.call_block_env(height)?;
+        let block_hashes = self.block_hashes_at(height)?;
+        let gas_limit = request.gas_limit(&block_env);
+        self.with_state_at(height, |state| {
+            let view = WorldView { state, block_hashes: &block_hashes };
+            self.simulate(view, &block_env, request, gas_limit, overrides)
+        })
+    }
+
+    // Smallest gas limit the call succeeds with: bisect between what it
+    // used at the cap and the cap, all against the same state
+    #[cfg(feature = "with-reth")]
+    pub fn estimate_gas(&self, request: &CallRequest, height: i64, overrides: &StateOverrides) -> Result<u64> {
+        let block_env = self.call_block_env(height)?;
+        let block_hashes = self.block_hashes_at(height)?;
+        let cap = request.gas_limit(&block_env);
+        self.with_state_at(height, |state| {
+            let view = WorldView { state, block_hashes: &block_hashes };
+            let at_cap = self.simulate(view, &block_env, request, cap, overrides)?;
+            if !at_cap.success {
+                return Err(Reverted { gas_limit: cap, output: at_cap.output }.into());
+            }
+
+            let mut lo = at_cap.gas_used.saturating_sub(1);
+            let mut hi = cap;
+            while lo + 1 < hi {
+                let mid = lo + (hi - lo) / 2;
+                match self.simulate(view, &block_env, request, mid, overrides) {
+                    Ok(receipt) if receipt.success => hi = mid,
+                    _ => lo = mid,
+                }
+            }
+            Ok(hi)
+        })
+    }
+
+    // BLOCKHASH window as block `height` saw it: the live one while
+    // `height` is the newest block in it, else rebuilt from the store
+    #[cfg(feature = "with-reth")]
+    fn block_hashes_at(&self, height: i64) -> Result<RecentHashes> {
+        {
+            let live = self.block_hashes.read().unwrap();
+            if live.newest() == height as u64 {
+                return Ok(live.clone());
+            }
         }
+        load_block_hashes(&self.store, height)
+    }
+
+    // Block context of a committed height, as the EVM saw it
+    #[cfg(feature = "with-reth")]
+    fn call_block_env(&self, height: i64) -> Result<BlockEnv> {
+        let meta = self.store.block(height)?.unwrap_or_default();
+        let gas_limit = match meta.gas_limit {
+            0 => self.block_gas_limit(),
+            limit => limit,
+        };
+        let coinbase = match meta.coinbase.len() {
+            20 => Address::from_slice(&meta.coinbase),
+            _ => Address::ZERO,
+        };
+        let block = BlockInfo {
+            height,
+            timestamp: meta.timestamp,
+            proposer: vec![],
+            hash: meta.hash,
+            parent_hash: meta.parent_hash,
+        };
+        Ok(BlockEnv {
+            number: U256::from(height),
+            coinbase,
+            timestamp: U256::from(meta.timestamp),
+            gas_limit: U256::from(gas_limit),
+            basefee: U256::from(meta.base_fee),
+            difficulty: U256::ZERO,
+            prevrandao: Some(block.prevrandao()),
+            blob_excess_gas_and_price: None,
+        })
+    }
+
+    #[cfg(feature = "with-reth")]
+    fn simulate(
+        &self,
+        view: WorldView<'_>,
+        block_env: &BlockEnv,
+        request: &CallRequest,
+        gas_limit: u64,
+        overrides: &StateOverrides,
+    ) -> Result<Receipt> {
+        let mut db = CacheDB::new(view);
+        apply_overrides(&mut db, overrides)?;
+
+        let tx_env = TxEnv {
+            caller: request.from.unwrap_or_default(),
+            gas_limit,
+            gas_price: request.gas_price.or(request.max_fee_per_gas).unwrap_or_default(),
+            transact_to: request.to.map_or(TxKind::Create, TxKind::Call),
+            value: request.value.unwrap_or_default(),
+            data: request.input.clone().unwrap_or_default(),
+            nonce: None,
+            chain_id: None,
+            access_list: vec![],
+            gas_priority_fee: request.max_priority_fee_per_gas,
+            blob_hashes: vec![],
+            max_fee_per_blob_gas: None,
+            authorization_list: None,
+        };
+
+        let timestamp = block_env.timestamp.saturating_to::<u64>();
+        let mut evm = Evm::builder()
+            .with_db(&mut db)
+            .with_spec_id(self.spec_id_at(timestamp))
+            .modify_cfg_env(|cfg| {
+                cfg.chain_id = self.chain_spec.chain().id();
+                // A zero gas price is allowed, and any `from` may call
+                cfg.disable_base_fee = true;
+                cfg.disable_eip3607 = true;
+            })
+            .with_block_env(block_env.clone())
+            .with_tx_env(tx_env)
+            .build();
+
+        // transact() leaves the db untouched; the overlay is dropped anyway
+        let result = evm
+            .transact()
+            .map_err(|e| anyhow::anyhow!("Call rejected by EVM: {:?}", e))?
+            .result;
+        Ok(Receipt::from_execution(result, 0))
     }
 }
 
+// Write state overrides into a call's overlay. `state` replaces the
+// account's whole storage, `state_diff` only the given slots.
+#[cfg(feature = "with-reth")]
+fn apply_overrides(db: &mut CacheDB<WorldView<'_>>, overrides: &StateOverrides) -> Result<()> {
+    for (address, account) in overrides {
+        if account.state.is_some() && account.state_diff.is_some() {
+            return Err(anyhow::anyhow!("Override for {} sets both state and stateDiff", address));
+        }
+
+        let mut info = db.basic(*address)?.unwrap_or_default();
+        if let Some(balance) = account.balance {
+            info.balance = balance;
+        }
+        if let Some(nonce) = account.nonce {
+            info.nonce = nonce.saturating_to();
+        }
+        if let Some(code) = &account.code {
+            info.code_hash = keccak256(code);
+            info.code = Some(Bytecode::new_raw(code.clone()));
+        }
+        db.insert_account_info(*address, info);
+
+        if let Some(state) = &account.state {
+            let storage = state
+                .iter()
+                .map(|(slot, value)| (U256::from_be_bytes(slot.0), U256::from_be_bytes(value.0)))
+                .collect();
+            db.replace_account_storage(*address, storage)?;
+        }
+        if let Some(diff) = &account.state_diff {
+            for (slot, value) in diff {
+                let (slot, value) = (U256::from_be_bytes(slot.0), U256::from_be_bytes(value.0));
+                db.insert_account_storage(*address, slot, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// BLOCKHASH window as of `height`: what a restored snapshot brought, then
+// the stored blocks on top
+#[cfg(feature = "with-reth")]
+fn load_block_hashes(store: &AppStore, height: i64) -> Result<RecentHashes> {
+    let mut hashes = store.restored_hashes()?.unwrap_or_default();
+    let oldest = (height - BLOCKHASH_WINDOW as i64).max(store.base_height()?);
+    for h in oldest.max(1)..=height {
+        if let Some(meta) = store.block(h)? {
+            hashes.insert(h as u64, B256::from(meta.hash));
+        }
+    }
+    Ok(hashes)
+}
+
+// Blob (EIP-4844) and SetCode (EIP-7702) txs decode, but neither blob gas
+// nor authorizations are implemented, and run as plain calls they would do
+// something else than on Ethereum
+#[cfg(feature = "with-reth")]
+fn check_tx_type(tx: &crate::wire::TxEnvelopeAny) -> Result<()> {
+    match tx.tx_type() {
+        TxType::Eip4844 | TxType::Eip7702 => {
+            Err(anyhow::anyhow!("Unsupported tx type {}", tx.tx_type() as u8))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Chain spec from the genesis recorded at InitChain (or carried by a
+// snapshot), else --genesis; chain id / fork overrides from the options
+// apply on top
+#[cfg(feature = "with-reth")]
+pub fn load_chain_spec(chain_opts: &ChainOptions, genesis: Option<Vec<u8>>) -> Result<ChainSpec> {
+    let base = genesis_source(chain_opts, genesis)?;
+    let base = base.as_deref().map(parse_genesis).transpose()?;
+    Ok(chain_opts.chain_spec(base))
+}
+
+// Hash of what load_chain_spec builds the chain spec from: the raw genesis
+// bytes and the overrides, each a presence byte then its bytes (u64s
+// big-endian, the genesis length-prefixed). The parsed genesis is not
+// hashed, as how it serializes changes with the alloy version.
+#[cfg(feature = "with-reth")]
+pub fn genesis_hash(chain_opts: &ChainOptions, genesis: Option<Vec<u8>>) -> Result<B256> {
+    let mut data = Vec::new();
+    match genesis_source(chain_opts, genesis)? {
+        Some(raw) => {
+            data.push(1);
+            data.extend_from_slice(&(raw.len() as u64).to_be_bytes());
+            data.extend_from_slice(&raw);
+        }
+        None => data.push(0),
+    }
+    let overrides = [
+        chain_opts.chain_id,
+        chain_opts.shanghai_time,
+        chain_opts.cancun_time,
+        chain_opts.prague_time,
+    ];
+    for value in overrides {
+        match value {
+            Some(value) => {
+                data.push(1);
+                data.extend_from_slice(&value.to_be_bytes());
+            }
+            None => data.push(0),
+        }
+    }
+    Ok(keccak256(&data))
+}
+
+#[cfg(feature = "with-reth")]
+fn genesis_source(chain_opts: &ChainOptions, genesis: Option<Vec<u8>>) -> Result<Option<Vec<u8>>> {
+    match genesis {
+        Some(raw) => Ok(Some(raw)),
+        None => chain_opts.genesis_file(),
+    }
+}
+
+// What the app hash commits to besides the state and receipts: everything
+// else the block after `meta` executes against. A snapshot is only restored
+// if it matches, so a peer can't hand a syncing node another chain spec,
+// base fee, block params or BLOCKHASH window.
+#[cfg(feature = "with-reth")]
+pub fn context_hash(genesis_hash: B256, meta: &BlockMeta, block_hashes: &RecentHashes) -> [u8; 32] {
+    let params = meta.block_params.unwrap_or_default();
+    let mut data = Vec::with_capacity(120);
+    data.extend_from_slice(genesis_hash.as_slice());
+    data.extend_from_slice(&meta.hash);
+    data.extend_from_slice(&meta.next_base_fee.to_be_bytes());
+    data.extend_from_slice(&params.max_bytes.to_be_bytes());
+    data.extend_from_slice(&params.max_gas.to_be_bytes());
+    data.extend_from_slice(block_hashes.digest().as_slice());
+    keccak256(&data).0
+}
+
+// Base fee of the first block: the genesis baseFeePerGas if set. Fees are
+// u64 from there on, so a larger one is a bad genesis.
+#[cfg(feature = "with-reth")]
+fn genesis_base_fee(genesis: &Genesis) -> Result<u64> {
+    match genesis.base_fee_per_gas {
+        Some(fee) => u64::try_from(fee)
+            .map_err(|_| anyhow::anyhow!("Genesis baseFeePerGas {} does not fit in 64 bits", fee)),
+        None => Ok(INITIAL_BASE_FEE),
+    }
+}
+
+#[cfg(feature = "with-reth")]
+fn parse_genesis(raw: &[u8]) -> Result<Genesis> {
+    serde_json::from_slice(raw).map_err(|e| anyhow::anyhow!("Invalid genesis: {}", e))
+}
+
+// A tx's share of max_tx_bytes as CometBFT counts it: the bytes plus the
+// protobuf field tag and length prefix
+fn proto_tx_size(len: usize) -> usize {
+    let mut varint = 1;
+    let mut rest = len >> 7;
+    while rest > 0 {
+        varint += 1;
+        rest >>= 7;
+    }
+    1 + varint + len
+}
+
+// What CheckTx learned about an admitted tx, for CometBFT's mempool
+pub struct CheckedTx {
+    pub sender: String,
+    pub nonce: u64,
+    pub gas_wanted: u64,
+    pub priority: i64,
+}
+
+pub struct Proposed {
+    pub txs: Vec<Vec<u8>>,
+}
+
+// eth_call / eth_estimateGas transaction object; unset fields get call
+// defaults (zero sender, zero price, the block gas limit)
+#[cfg(feature = "with-reth")]
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRequest {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub gas: Option<U256>,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub value: Option<U256>,
+    #[serde(alias = "data")]
+    pub input: Option<Bytes>,
+}
+
+#[cfg(feature = "with-reth")]
+impl CallRequest {
+    // Requested gas, capped at the block gas limit
+    fn gas_limit(&self, block_env: &BlockEnv) -> u64 {
+        let block_limit = block_env.gas_limit.saturating_to::<u64>();
+        self.gas.map_or(block_limit, |gas| gas.saturating_to::<u64>().min(block_limit))
+    }
+}
+
+// Per-account state overrides for a call (geth's eth_call third argument)
+#[cfg(feature = "with-reth")]
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    pub balance: Option<U256>,
+    pub nonce: Option<U256>,
+    pub code: Option<Bytes>,
+    pub state: Option<HashMap<B256, B256>>,
+    pub state_diff: Option<HashMap<B256, B256>>,
+}
+
+#[cfg(feature = "with-reth")]
+pub type StateOverrides = HashMap<Address, AccountOverride>;
+
+// Consensus-side facts about a block, available both when proposing (no
+// header yet) and when executing a decided block
 #[derive(Clone)]
+pub struct BlockInfo {
+    pub height: i64,
+    pub timestamp: u64,
+    pub proposer: Vec<u8>,
+    // CometBFT block hash; zero while the block is only being proposed
+    pub hash: [u8; 32],
+    // Hash of the previous block (zero at the first height)
+    pub parent_hash: [u8; 32],
+}
+
+impl BlockInfo {
+    pub fn from_header(header: &TmHeader) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(header.hash().as_bytes());
+        let mut parent_hash = [0u8; 32];
+        if let Some(id) = &header.last_block_id {
+            parent_hash.copy_from_slice(id.hash.as_bytes());
+        }
+        Self {
+            height: header.height.value() as i64,
+            timestamp: header.time.unix_timestamp() as u64,
+            proposer: header.proposer_address.as_bytes().to_vec(),
+            hash,
+            parent_hash,
+        }
+    }
+
+    // block.prevrandao = keccak256(parent_hash || height). Known to everyone
+    // once the parent is committed, so identical in PrepareProposal,
+    // ProcessProposal and DeliverTx. See "Randomness" in the README for why
+    // it is not a secure source of randomness.
+    #[cfg(feature = "with-reth")]
+    pub fn prevrandao(&self) -> B256 {
+        let mut preimage = Vec::with_capacity(40);
+        preimage.extend_from_slice(&self.parent_hash);
+        preimage.extend_from_slice(&(self.height as u64).to_be_bytes());
+        keccak256(&preimage)
+    }
+}
+
+pub struct BlockExec {
+    block: BlockInfo,
+    receipts: Vec<Receipt>,
+    gas_used: u64,
+    gas_limit: u64,
+    base_fee: u64,
+    state_root: [u8; 32],
+    
+    #[cfg(feature = "with-reth")]
+    executed_txs: Vec<TransactionSigned>,
+
+    // How many of executed_txs DeliverTx has handed out. Behind
+    // executed_txs.len() only when replaying a block simulated in
+    // ProcessProposal.
+    #[cfg(feature = "with-reth")]
+    delivered: usize,
+
+    // Consensus-encoded receipts, in tx order, for the receipts trie
+    #[cfg(feature = "with-reth")]
+    eth_receipts: Vec<EthReceipt>,
+
+    #[cfg(feature = "with-reth")]
+    block_env: BlockEnv,
+
+    // Per-block writes (balances, nonces, code, storage) on top of committed state
+    #[cfg(feature = "with-reth")]
+    overlay: CacheDB<StateView>,
+}
+
+impl BlockExec {
+    pub fn new(reth: &RethCtx, block: BlockInfo) -> Self {
+        #[cfg(feature = "with-reth")]
+        let block_env = BlockEnv {
+            number: U256::from(block.height),
+            // revm credits the priority fee (effective tip * gas used) of
+            // every tx to the coinbase; the base fee part is burned
+            coinbase: reth.fee_recipient(&block.proposer),
+            timestamp: U256::from(block.timestamp),
+            gas_limit: U256::from(reth.block_gas_limit()),
+            basefee: U256::from(reth.base_fee()),
+            difficulty: U256::ZERO, // Post-merge
+            prevrandao: Some(block.prevrandao()),
+            blob_excess_gas_and_price: None,
+        };
+
+        Self {
+            block,
+            receipts: vec![],
+            gas_used: 0,
+            gas_limit: reth.block_gas_limit(),
+            base_fee: reth.base_fee(),
+            state_root: [0u8; 32],
+            #[cfg(feature = "with-reth")]
+            executed_txs: vec![],
+            #[cfg(feature = "with-reth")]
+            delivered: 0,
+            #[cfg(feature = "with-reth")]
+            eth_receipts: vec![],
+            #[cfg(feature = "with-reth")]
+            block_env,
+            #[cfg(feature = "with-reth")]
+            overlay: CacheDB::new(StateView::new(reth.state.clone(), reth.block_hashes.clone())),
+        }
+    }
+
+    pub fn height(&self) -> i64 {
+        self.block.height
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        self.block.hash
+    }
+
+    pub fn parent_hash(&self) -> [u8; 32] {
+        self.block.parent_hash
+    }
+
+    // Ethereum hashes of the txs executed so far. The stub executes
+    // nothing, so it has none.
+    pub fn tx_hashes(&self) -> Vec<[u8; 32]> {
+        #[cfg(feature = "with-reth")]
+        return self.executed_txs.iter().map(|tx| tx.hash().0).collect();
+
+        #[cfg(not(feature = "with-reth"))]
+        vec![]
+    }
+
+    pub fn receipts(&self) -> &[Receipt] {
+        &self.receipts
+    }
+
+    // EVM address credited with the block's priority fees
+    pub fn coinbase(&self) -> Vec<u8> {
+        #[cfg(feature = "with-reth")]
+        return self.block_env.coinbase.to_vec();
+
+        #[cfg(not(feature = "with-reth"))]
+        vec![0u8; 20]
+    }
+
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used
+    }
+
+    pub fn gas_limit(&self) -> u64 {
+        self.gas_limit
+    }
+
+    pub fn base_fee(&self) -> u64 {
+        self.base_fee
+    }
+
+    // EIP-1559: move the base fee by up to 1/8 toward keeping blocks at half
+    // the gas limit. Increases are at least 1 wei; decreases round down, so
+    // the fee never falls below 7 wei (a 1/8 step of 7 is 0).
+    pub fn next_base_fee(&self) -> u64 {
+        let target = self.gas_limit / ELASTICITY_MULTIPLIER;
+        if target == 0 {
+            return self.base_fee;
+        }
+        let base_fee = self.base_fee as u128;
+        if self.gas_used > target {
+            let delta = base_fee * (self.gas_used - target) as u128
+                / target as u128
+                / BASE_FEE_MAX_CHANGE_DENOMINATOR as u128;
+            self.base_fee.saturating_add(delta.max(1) as u64)
+        } else {
+            let delta = base_fee * (target - self.gas_used) as u128
+                / target as u128
+                / BASE_FEE_MAX_CHANGE_DENOMINATOR as u128;
+            self.base_fee - delta as u64
+        }
+    }
+
+    // Turn a simulated block into one DeliverTx can replay: the overlay and
+    // receipts are kept, and each delivered tx is matched against them
+    // instead of being executed again
+    pub fn rewind(mut self) -> Self {
+        #[cfg(feature = "with-reth")]
+        {
+            self.delivered = 0;
+        }
+        self
+    }
+
+    // The overlay already holds the txs simulated in ProcessProposal, so a
+    // delivered tx that differs from them is a state failure, not a tx one
+    pub fn apply_tx(
+        &mut self,
+        reth: &RethCtx,
+        tx: crate::wire::TxEnvelopeAny,
+    ) -> Result<Receipt, ExecError> {
+        #[cfg(feature = "with-reth")]
+        {
+            if let Some(executed) = self.executed_txs.get(self.delivered) {
+                if executed.hash() != tx.hash() {
+                    return Err(ExecError::State(anyhow::anyhow!(
+                        "Delivered tx {} does not match simulated tx {}",
+                        tx.hash(),
+                        executed.hash()
+                    )));
+                }
+                let receipt = self.receipts[self.delivered].clone();
+                self.delivered += 1;
+                return Ok(receipt);
+            }
+
+            check_tx_type(&tx).map_err(ExecError::Tx)?;
+            let remaining = self.gas_limit.saturating_sub(self.gas_used);
+            if tx.gas_limit() > remaining {
+                return Err(ExecError::Tx(anyhow::anyhow!(
+                    "Tx gas limit {} exceeds remaining block gas {}",
+                    tx.gas_limit(),
+                    remaining
+                )));
+            }
+
+            // Create transaction environment
+            let caller = tx
+                .recover_signer()
+                .map_err(|e| ExecError::Tx(anyhow::anyhow!("Invalid signature: {}", e)))?;
+            let tx_env = TxEnv {
+                caller,
+                gas_limit: tx.gas_limit(),
+                gas_price: U256::from(tx.max_fee_per_gas()),
+                transact_to: tx.to().map_or(TxKind::Create, TxKind::Call),
+                value: tx.value(),
+                data: tx.input().clone(),
+                nonce: Some(tx.nonce()),
+                // revm rejects a tx signed for another chain; pre-EIP-155
+                // legacy txs carry none and run anywhere, as on Ethereum
+                chain_id: tx.chain_id(),
+                access_list: tx.access_list().cloned().unwrap_or_default().0,
+                gas_priority_fee: tx.max_priority_fee_per_gas().map(U256::from),
+                blob_hashes: vec![],
+                max_fee_per_blob_gas: None,
+                authorization_list: None,
+            };
+
+            let timestamp = self.block.timestamp;
+            let mut evm = Evm::builder()
+                .with_db(&mut self.overlay)
+                .with_spec_id(reth.spec_id_at(timestamp))
+                .modify_cfg_env(|cfg| cfg.chain_id = reth.chain_spec.chain().id())
+                .with_block_env(self.block_env.clone())
+                .with_tx_env(tx_env)
+                .build();
+
+            // Invalid txs (bad nonce, can't afford gas, ...) error out here and
+            // leave the overlay untouched; reverts and halts still commit the
+            // nonce bump and gas payment, as on Ethereum. Only a failing
+            // state read is fatal.
+            let result = evm.transact_commit().map_err(|e| match e {
+                EVMError::Database(e) => {
+                    ExecError::State(anyhow::anyhow!("State read failed: {:?}", e))
+                }
+                e => ExecError::Tx(anyhow::anyhow!("Transaction rejected by EVM: {:?}", e)),
+            })?;
+            drop(evm);
+
+            self.eth_receipts.push(EthReceipt {
+                tx_type: tx.tx_type(),
+                success: result.is_success(),
+                cumulative_gas_used: self.gas_used + result.gas_used(),
+                logs: result.logs().to_vec(),
+            });
+            // Log indexes run across the whole block, as in eth_getLogs
+            let first_log_index = self.receipts.iter().map(|r| r.logs.len() as u64).sum();
+            let mut receipt = Receipt::from_execution(result, first_log_index);
+            receipt.tx_hash = tx.hash().to_vec();
+            receipt.tx_index = self.receipts.len() as u64;
+            receipt.tx_type = tx.tx_type() as u8;
+            receipt.from = caller.to_vec();
+            receipt.to = tx.to().map(|a| a.to_vec());
+            receipt.nonce = tx.nonce();
+            receipt.gas_limit = tx.gas_limit();
+            receipt.cumulative_gas_used = self.gas_used + receipt.gas_used;
+            receipt.effective_gas_price = tx.effective_gas_price(Some(self.base_fee));
+
+            self.receipts.push(receipt.clone());
+            self.gas_used += receipt.gas_used;
+            self.executed_txs.push(tx.into_signed());
+            self.delivered += 1;
+            
+            Ok(receipt)
+        }
+        
+        #[cfg(not(feature = "with-reth"))]
+        {
+            let _ = tx;
+            Ok(Receipt::ok())
+        }
+    }
+
+    // Fold the block into `world`, the committed state its overlay sits on
+    #[cfg(feature = "with-reth")]
+    pub fn commit(self, world: &mut WorldState) -> Result<Committed, ExecError> {
+        if self.delivered != self.executed_txs.len() {
+            return Err(ExecError::State(anyhow::anyhow!(
+                "Only {} of {} simulated txs were delivered",
+                self.delivered,
+                self.executed_txs.len()
+            )));
+        }
+
+        // Flush the block overlay into the committed state
+        let diff = world.apply_overlay(self.overlay);
+
+        let state_root = world.state_root().0;
+        let receipts_with_bloom: Vec<_> = self
+            .eth_receipts
+            .into_iter()
+            .map(EthReceipt::with_bloom)
+            .collect();
+        let receipts_root = calculate_receipt_root(&receipts_with_bloom).0;
+
+        Ok(Committed {
+            state_root,
+            receipts_root,
+            gas_used: self.gas_used,
+            timestamp: self.block.timestamp,
+            diff,
+        })
+    }
+
+    #[cfg(not(feature = "with-reth"))]
+    pub fn commit(self) -> Result<Committed, ExecError> {
+        Ok(Committed {
+            state_root: [0u8; 32],
+            receipts_root: [0u8; 32],
+            gas_used: self.gas_used,
+            timestamp: 0,
+        })
+    }
+}
+
+// A block folded into the committed state
+pub struct Committed {
+    pub state_root: [u8; 32],
+    pub receipts_root: [u8; 32],
+    pub gas_used: u64,
+    pub timestamp: u64,
+    // What the block changed, stored in place of the full state
+    #[cfg(feature = "with-reth")]
+    pub diff: StateDiff,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Receipt {
+    #[serde(with = "crate::store::hex_bytes")]
+    pub tx_hash: Vec<u8>,
+    // Position of the tx in its block
+    pub tx_index: u64,
+    // EIP-2718 type
+    pub tx_type: u8,
+    #[serde(with = "crate::store::hex_bytes")]
+    pub from: Vec<u8>,
+    // None for contract creation
+    #[serde(with = "crate::store::hex_bytes_opt")]
+    pub to: Option<Vec<u8>>,
+    pub nonce: u64,
     pub success: bool,
+    // The tx's own gas limit, reported as DeliverTx's gas_wanted
+    #[serde(default)]
+    pub gas_limit: u64,
     pub gas_used: u64,
+    // Gas used by this tx and every tx before it in the block
+    pub cumulative_gas_used: u64,
+    // Wei per gas actually paid: base fee plus effective tip
+    pub effective_gas_price: u128,
     pub logs: Vec<Log>,
+    // Return data, or revert data when the call failed
+    #[serde(with = "crate::store::hex_bytes")]
+    pub output: Vec<u8>,
+    #[serde(with = "crate::store::hex_bytes_opt")]
+    pub contract_address: Option<Vec<u8>>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Log {
+    #[serde(with = "crate::store::hex_bytes")]
     pub address: Vec<u8>,
+    #[serde(with = "crate::store::hex_bytes_vec")]
     pub topics: Vec<Vec<u8>>,
+    #[serde(with = "crate::store::hex_bytes")]
     pub data: Vec<u8>,
+    // Block-wide log index
+    pub index: u64,
 }
 
 impl Receipt {
     pub fn ok() -> Self {
         Self {
+            tx_hash: vec![],
+            tx_index: 0,
+            tx_type: 0,
+            from: vec![],
+            to: None,
+            nonce: 0,
             success: true,
+            gas_limit: 21000,
             gas_used: 21000,
+            cumulative_gas_used: 0,
+            effective_gas_price: 0,
             logs: vec![],
+            output: vec![],
+            contract_address: None,
         }
     }
-    
+
+    // Tx identity and block position are left for the caller to fill in
+    #[cfg(feature = "with-reth")]
+    fn from_execution(result: ExecutionResult, first_log_index: u64) -> Self {
+        let (success, gas_used, logs, output, contract_address) = match result {
+            ExecutionResult::Success { gas_used, logs, output, .. } => {
+                let contract_address = match &output {
+                    Output::Create(_, address) => address.map(|a| a.to_vec()),
+                    Output::Call(_) => None,
+                };
+                let logs = logs
+                    .into_iter()
+                    .zip(first_log_index..)
+                    .map(|(log, index)| Log {
+                        address: log.address.to_vec(),
+                        topics: log.topics().iter().map(|t| t.to_vec()).collect(),
+                        data: log.data.data.to_vec(),
+                        index,
+                    })
+                    .collect();
+                (true, gas_used, logs, output.into_data().to_vec(), contract_address)
+            }
+            ExecutionResult::Revert { gas_used, output } => {
+                (false, gas_used, vec![], output.to_vec(), None)
+            }
+            ExecutionResult::Halt { gas_used, .. } => (false, gas_used, vec![], vec![], None),
+        };
+
+        Self {
+            success,
+            gas_used,
+            logs,
+            output,
+            contract_address,
+            ..Self::ok()
+        }
+    }
+
+    // One `ethereum.tx` event per tx and one `ethereum.log` event per log.
+    // Hashes, addresses, topics and data are 0x-prefixed lowercase hex so
+    // they can be matched verbatim in tx_search.
     pub fn into_abci_events(self) -> Vec<AbciEvent> {
         let mut events = Vec::new();
-        
-        // Add transaction event
-        let tx_event = AbciEvent {
+
+        let tx_hash = hex0x(&self.tx_hash);
+        let mut tx_attrs = vec![
+            attr("hash", tx_hash.clone(), true),
+            attr("index", self.tx_index.to_string(), false),
+            attr("from", hex0x(&self.from), true),
+        ];
+        if let Some(to) = &self.to {
+            tx_attrs.push(attr("to", hex0x(to), true));
+        }
+        tx_attrs.push(attr("nonce", self.nonce.to_string(), false));
+        if let Some(address) = &self.contract_address {
+            tx_attrs.push(attr("contract_address", hex0x(address), true));
+        }
+        // Same meaning as the Ethereum receipt field: 1 success, 0 failure
+        tx_attrs.push(attr("status", if self.success { "1" } else { "0" }.into(), true));
+        tx_attrs.push(attr("success", self.success.to_string(), true));
+        tx_attrs.push(attr("gas_used", self.gas_used.to_string(), false));
+
+        events.push(AbciEvent {
             r#type: "ethereum.tx".to_string(),
-            attributes: vec![
-                tendermint_proto::abci::EventAttribute {
-                    key: "success".into(),
-                    value: self.success.to_string().into(),
-                    index: true,
-                },
-                tendermint_proto::abci::EventAttribute {
-                    key: "gas_used".into(),
-                    value: self.gas_used.to_string().into(),
-                    index: false,
-                },
-            ],
-        };
-        events.push(tx_event);
-        
-        // Add log events
+            attributes: tx_attrs,
+        });
+
         for log in self.logs {
-            let log_event = AbciEvent {
+            let mut attributes = vec![
+                attr("address", hex0x(&log.address), true),
+                attr("tx_hash", tx_hash.clone(), true),
+                attr("log_index", log.index.to_string(), false),
+            ];
+            for (i, topic) in log.topics.iter().enumerate() {
+                attributes.push(attr(&format!("topic{}", i), hex0x(topic), true));
+            }
+            // Arbitrary size, so returned but not indexed
+            attributes.push(attr("data", hex0x(&log.data), false));
+
+            events.push(AbciEvent {
                 r#type: "ethereum.log".to_string(),
-                attributes: vec![
-                    tendermint_proto::abci::EventAttribute {
-                        key: "address".into(),
-                        value: hex::encode(&log.address).into(),
-                        index: true,
-                    },
-                ],
-            };
-            events.push(log_event);
+                attributes,
+            });
         }
-        
+
         events
     }
-}
\ No newline at end of file
+}
+
+fn attr(key: &str, value: String, index: bool) -> tendermint_proto::abci::EventAttribute {
+    tendermint_proto::abci::EventAttribute {
+        key: key.into(),
+        value: value.into(),
+        index,
+    }
+}
+
+pub fn hex0x(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "with-reth")]
+    use crate::testutil::*;
+    #[cfg(feature = "with-reth")]
+    use reth_primitives::{Transaction, TxEip1559, TxEip7702};
+
+    // Base fee after a block with `gas_used` of `gas_limit`
+    fn next_base_fee(base_fee: u64, gas_limit: u64, gas_used: u64) -> u64 {
+        let dir = tempfile::TempDir::new().unwrap();
+        let reth = RethCtx::open(dir.path().to_str().unwrap(), ChainOptions::default()).unwrap();
+        let block = BlockInfo {
+            height: 1,
+            timestamp: 0,
+            proposer: vec![],
+            hash: [1u8; 32],
+            parent_hash: [0u8; 32],
+        };
+        let mut exec = BlockExec::new(&reth, block);
+        exec.base_fee = base_fee;
+        exec.gas_limit = gas_limit;
+        exec.gas_used = gas_used;
+        exec.next_base_fee()
+    }
+
+    #[test]
+    fn base_fee_holds_at_target() {
+        assert_eq!(next_base_fee(1_000_000_000, 30_000_000, 15_000_000), 1_000_000_000);
+    }
+
+    #[test]
+    fn base_fee_rises_above_target() {
+        assert_eq!(next_base_fee(1_000_000_000, 30_000_000, 30_000_000), 1_125_000_000);
+        assert_eq!(next_base_fee(1_000_000_000, 30_000_000, 22_500_000), 1_062_500_000);
+        // Even a barely-above-target block moves it by 1 wei
+        assert_eq!(next_base_fee(8, 30_000_000, 15_000_001), 9);
+    }
+
+    #[test]
+    fn base_fee_falls_below_target() {
+        assert_eq!(next_base_fee(1_000_000_000, 30_000_000, 0), 875_000_000);
+        assert_eq!(next_base_fee(1_000_000_000, 30_000_000, 7_500_000), 937_500_000);
+    }
+
+    #[test]
+    fn base_fee_floors_at_seven_wei() {
+        assert_eq!(next_base_fee(8, 30_000_000, 0), 7);
+        assert_eq!(next_base_fee(7, 30_000_000, 0), 7);
+        assert_eq!(next_base_fee(0, 30_000_000, 30_000_000), 1);
+        // No target to steer toward
+        assert_eq!(next_base_fee(7, 1, 1), 7);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn check_tx_rejects_underpriced_and_replacement_txs() {
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+
+        let underpriced = transfer(key(1), 0, BASE_FEE - 1, 0, 21_000);
+        let err = reth.check_tx(&underpriced).unwrap_err();
+        assert!(err.to_string().contains("below base fee"), "{}", err);
+
+        reth.check_tx(&transfer(key(1), 0, BASE_FEE, 1, 21_000)).unwrap();
+        // Pending nonces can't be replaced, not even at a higher fee
+        assert!(reth.check_tx(&transfer(key(1), 0, 2 * BASE_FEE, 2, 21_000)).is_err());
+        reth.check_tx(&transfer(key(1), 1, BASE_FEE, 1, 21_000)).unwrap();
+        // Nor skipped
+        assert!(reth.check_tx(&transfer(key(1), 3, BASE_FEE, 1, 21_000)).is_err());
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn recheck_keeps_txs_rechecked_out_of_nonce_order() {
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+        let txs: Vec<_> = (0..3)
+            .map(|nonce| transfer(key(1), nonce, BASE_FEE, 1, 21_000))
+            .collect();
+        for tx in &txs {
+            reth.check_tx(tx).unwrap();
+        }
+
+        // Nonce 0 is committed; CometBFT rechecks the rest, 2 before 1
+        commit(&reth, 1, &[raw(&txs[0])]);
+        reth.reset_pending();
+        assert!(reth.recheck_tx(&txs[0]).is_err());
+        reth.recheck_tx(&txs[2]).unwrap();
+        reth.recheck_tx(&txs[1]).unwrap();
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn genesis_hash_covers_the_raw_genesis_and_overrides() {
+        let raw = genesis(&[key(1)], serde_json::json!({})).to_string().into_bytes();
+        let opts = ChainOptions::default();
+        let hash = genesis_hash(&opts, Some(raw.clone())).unwrap();
+        assert_eq!(genesis_hash(&opts, Some(raw.clone())).unwrap(), hash);
+
+        let chain_id = ChainOptions { chain_id: Some(CHAIN_ID), ..Default::default() };
+        let cancun = ChainOptions { cancun_time: Some(0), ..Default::default() };
+        for (case, opts, raw) in [
+            ("chain id override", &chain_id, Some(raw.clone())),
+            ("fork override", &cancun, Some(raw.clone())),
+            ("no genesis", &opts, None),
+            ("other genesis", &opts, Some([raw.as_slice(), b" "].concat())),
+        ] {
+            assert_ne!(genesis_hash(opts, raw).unwrap(), hash, "{}", case);
+        }
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn fee_recipients_come_from_genesis() {
+        let recipient = Address::with_last_byte(0x42);
+        let mut recipients = serde_json::Map::new();
+        recipients.insert(hex::encode_upper([0xaa; 20]), serde_json::json!(recipient));
+        let extra = serde_json::json!({ "feeRecipients": recipients });
+        let (_dir, reth) = node(&genesis(&[], extra));
+
+        assert_eq!(reth.fee_recipient(&[0xaa; 20]), recipient);
+        // Unlisted proposers burn their fees
+        assert_eq!(reth.fee_recipient(&[0xbb; 20]), Address::ZERO);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn propose_block_orders_by_tip_within_nonce_order() {
+        let (_dir, reth) = node(&genesis(&[key(1), key(2)], serde_json::json!({})));
+        let first = transfer(key(1), 0, 3 * BASE_FEE, 1, 21_000);
+        // Best tip of all, but can't go before its sender's nonce 0
+        let second = transfer(key(1), 1, 3 * BASE_FEE, 100, 21_000);
+        let other = transfer(key(2), 0, 3 * BASE_FEE, 50, 21_000);
+        // Never includable at the current base fee
+        let underpriced = transfer(key(2), 1, BASE_FEE - 1, 0, 21_000);
+
+        let txs = vec![raw(&second), raw(&underpriced), raw(&first), raw(&other)];
+        let proposed = reth.propose_block(&txs, usize::MAX, block(1));
+        assert_eq!(proposed.txs, vec![raw(&other), raw(&first), raw(&second)]);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn block_params_update_only_fills_an_unlimited_max_gas() {
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+
+        let update = reth.block_params_update().unwrap();
+        assert_eq!(update, BlockParams { max_gas: GAS_LIMIT as i64, ..BlockParams::default() });
+
+        // InitChain's max_gas wins over the genesis gasLimit
+        reth.set_block_params(BlockParams { max_bytes: 1 << 20, max_gas: 50_000 }).unwrap();
+        assert_eq!(reth.block_params_update(), None);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn propose_block_caps_gas_and_bytes() {
+        let (_dir, reth) = node(&genesis(&[key(1), key(2)], serde_json::json!({})));
+        reth.set_block_params(BlockParams { max_bytes: 1 << 20, max_gas: 50_000 }).unwrap();
+
+        let txs: Vec<_> = (0..3)
+            .map(|nonce| raw(&transfer(key(1), nonce, 2 * BASE_FEE, 1, 21_000)))
+            .collect();
+        // Best tip, but asks for more gas than a block has
+        let big = raw(&transfer(key(2), 0, 2 * BASE_FEE, 10, 60_000));
+
+        // Two transfers fit in 50k gas, the third doesn't
+        let all = [vec![big], txs.clone()].concat();
+        let proposed = reth.propose_block(&all, usize::MAX, block(1));
+        assert_eq!(proposed.txs, txs[..2].to_vec());
+
+        let max_bytes = proto_tx_size(txs[0].len()) * 2 - 1;
+        let proposed = reth.propose_block(&txs, max_bytes, block(1));
+        assert_eq!(proposed.txs, txs[..1].to_vec());
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn validate_proposal_accepts_valid_block() {
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+        let txs: Vec<_> = (0..2)
+            .map(|nonce| raw(&transfer(key(1), nonce, 2 * BASE_FEE, 1, 21_000)))
+            .collect();
+
+        let exec = reth.validate_proposal(&txs, block(1)).unwrap();
+        assert_eq!(exec.receipts().len(), 2);
+        assert_eq!(exec.gas_used(), 42_000);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn validate_proposal_rejects_invalid_blocks() {
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+        reth.set_block_params(BlockParams { max_bytes: 1 << 20, max_gas: 50_000 }).unwrap();
+        let tx = |nonce, max_fee, gas_limit| raw(&transfer(key(1), nonce, max_fee, 1, gas_limit));
+        let ok = 2 * BASE_FEE;
+
+        let rejected = [
+            ("undecodable", vec![vec![0x02, 0xc0]]),
+            ("duplicate", vec![tx(0, ok, 21_000), tx(0, ok, 21_000)]),
+            ("below base fee", vec![tx(0, BASE_FEE - 1, 21_000)]),
+            ("nonce gap", vec![tx(0, ok, 21_000), tx(2, ok, 21_000)]),
+            ("over block gas", vec![tx(0, ok, 21_000), tx(1, ok, 21_000), tx(2, ok, 21_000)]),
+            ("gas overflow", vec![tx(0, ok, 21_000), tx(1, ok, u64::MAX)]),
+        ];
+        for (case, txs) in rejected {
+            assert!(reth.validate_proposal(&txs, block(1)).is_err(), "{} accepted", case);
+        }
+
+        // A tx replayed from another chain, which only CheckTx used to check
+        let other_chain = TxEip1559 {
+            chain_id: CHAIN_ID + 1,
+            max_fee_per_gas: ok,
+            gas_limit: 21_000,
+            to: TxKind::Call(Address::with_last_byte(0xff)),
+            ..Default::default()
+        };
+        let replayed = sign(key(1), Transaction::Eip1559(other_chain));
+        assert!(reth.validate_proposal(&[raw(&replayed)], block(1)).is_err(), "replay accepted");
+        assert!(reth.propose_block(&[raw(&replayed)], usize::MAX, block(1)).txs.is_empty());
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn replaying_a_validated_proposal_matches_fresh_execution() {
+        let genesis = genesis(&[key(1), key(2)], serde_json::json!({}));
+        let init = [0x60, 0x2a, 0x60, 0x00, 0x53, 0x60, 0x01, 0x60, 0x00, 0xf3];
+        let txs = vec![
+            raw(&transfer(key(1), 0, 2 * BASE_FEE, 1, 21_000)),
+            raw(&call(key(2), 0, TxKind::Create, &init)),
+            raw(&transfer(key(1), 1, 2 * BASE_FEE, 2, 21_000)),
+        ];
+
+        // ProcessProposal's execution, reused by DeliverTx
+        let (_replayed_dir, replayed) = node(&genesis);
+        let reused = commit(&replayed, 1, &txs);
+
+        let (_fresh_dir, fresh) = node(&genesis);
+        let mut exec = BlockExec::new(&fresh, block(1));
+        for tx in &txs {
+            exec.apply_tx(&fresh, crate::wire::decode_eth_tx(tx).unwrap()).unwrap();
+        }
+        let executed = fresh.commit_block(exec, fresh.block_params()).unwrap();
+
+        assert_eq!(reused.app_hash, executed.app_hash);
+        assert_eq!(reused.state_root, executed.state_root);
+        assert_eq!(reused.receipts_root, executed.receipts_root);
+        assert_eq!(reused.gas_used, executed.gas_used);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn delivering_other_txs_than_validated_is_a_state_failure() {
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+        let txs: Vec<_> = (0..2)
+            .map(|nonce| raw(&transfer(key(1), nonce, 2 * BASE_FEE, 1, 21_000)))
+            .collect();
+
+        let mut exec = reth.validate_proposal(&txs, block(1)).unwrap();
+        let out_of_order = crate::wire::decode_eth_tx(&txs[1]).unwrap();
+        let err = exec.apply_tx(&reth, out_of_order).unwrap_err();
+        assert!(matches!(err, ExecError::State(_)), "{}", err);
+        assert!(err.to_string().contains("does not match simulated tx"), "{}", err);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn reverted_calls_charge_gas_and_keep_the_revert_data() {
+        // mstore(0, 0x2a) revert(0, 32)
+        let code = [0x60, 0x2a, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xfd];
+        let reverter = Address::with_last_byte(0xee);
+        let genesis = with_code(genesis(&[key(1)], serde_json::json!({})), reverter, &code);
+        let (_dir, reth) = node(&genesis);
+        let sender = address(key(1));
+        let funded = reth.state.read().unwrap().account(&sender).unwrap().balance;
+
+        let meta = commit(&reth, 1, &[raw(&call(key(1), 0, TxKind::Call(reverter), &[]))]);
+        let receipt = &reth.store.receipts(1).unwrap().unwrap()[0];
+        assert!(!receipt.success);
+        assert_eq!(receipt.output, U256::from(0x2a).to_be_bytes::<32>().to_vec());
+        assert!(receipt.gas_used > 21_000);
+        assert_eq!(receipt.gas_limit, 200_000);
+        assert_eq!(meta.gas_used, receipt.gas_used);
+
+        // The nonce is used and the gas paid all the same
+        let state = reth.state.read().unwrap();
+        let account = state.account(&sender).unwrap();
+        assert_eq!(account.nonce, 1);
+        let fee = U256::from(receipt.gas_used) * U256::from(receipt.effective_gas_price);
+        assert_eq!(account.balance, funded - fee);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn create_deploys_the_returned_code() {
+        // mstore8(0, 0x2a) return(0, 1)
+        let init = [0x60, 0x2a, 0x60, 0x00, 0x53, 0x60, 0x01, 0x60, 0x00, 0xf3];
+        let (_dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+
+        commit(&reth, 1, &[raw(&call(key(1), 0, TxKind::Create, &init))]);
+        let receipt = &reth.store.receipts(1).unwrap().unwrap()[0];
+        let deployed = address(key(1)).create(0);
+        assert!(receipt.success);
+        assert_eq!(receipt.to, None);
+        assert_eq!(receipt.contract_address, Some(deployed.to_vec()));
+
+        let state = reth.state.read().unwrap();
+        assert_eq!(state.account(&deployed).unwrap().code.to_vec(), vec![0x2a]);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn log_indexes_run_across_the_block() {
+        // log1(0, 0, 1) log1(0, 0, 2)
+        let code = [
+            0x60, 0x01, 0x60, 0x00, 0x60, 0x00, 0xa1, //
+            0x60, 0x02, 0x60, 0x00, 0x60, 0x00, 0xa1, //
+            0x00,
+        ];
+        let logger = Address::with_last_byte(0xee);
+        let genesis = with_code(genesis(&[key(1)], serde_json::json!({})), logger, &code);
+        let (_dir, reth) = node(&genesis);
+
+        let txs: Vec<_> = (0..2)
+            .map(|nonce| raw(&call(key(1), nonce, TxKind::Call(logger), &[])))
+            .collect();
+        commit(&reth, 1, &txs);
+        let receipts = reth.store.receipts(1).unwrap().unwrap();
+        let indexes: Vec<Vec<u64>> = receipts
+            .iter()
+            .map(|receipt| receipt.logs.iter().map(|log| log.index).collect())
+            .collect();
+        assert_eq!(indexes, vec![vec![0, 1], vec![2, 3]]);
+
+        let log = &receipts[1].logs[1];
+        assert_eq!(log.address, logger.to_vec());
+        assert_eq!(log.topics, vec![U256::from(2).to_be_bytes::<32>().to_vec()]);
+    }
+
+    // What a node reopened on the same dir has to agree on with the one
+    // that committed: height, app hash rebuilt from the resumed state and
+    // window, state root, base fee and the BLOCKHASH window
+    #[cfg(feature = "with-reth")]
+    fn resume_point(reth: &RethCtx) -> (i64, [u8; 32], B256, u64, B256) {
+        let latest = reth.store.latest().unwrap().unwrap();
+        let state_root = reth.state.read().unwrap().state_root();
+        let block_hashes = reth.block_hashes.read().unwrap().clone();
+        let context = context_hash(reth.genesis_hash, &latest, &block_hashes);
+        let app_hash = apphash_from(state_root.0, latest.receipts_root, context);
+        assert_eq!(app_hash, latest.app_hash);
+        (latest.height, app_hash, state_root, reth.base_fee(), block_hashes.digest())
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn reopening_resumes_across_a_checkpoint_and_a_torn_commit() {
+        let (dir, reth) = node(&genesis(&[key(1)], serde_json::json!({})));
+        let path = dir.path().to_str().unwrap();
+        let send = |nonce, tip| raw(&transfer(key(1), nonce, BASE_FEE, tip, 21_000));
+
+        commit(&reth, 1, &[send(0, 1)]);
+        for height in 2..STATE_CHECKPOINT_INTERVAL {
+            commit(&reth, height, &[]);
+        }
+        let before = reth.store.latest().unwrap().unwrap();
+        let expected = resume_point(&reth);
+
+        // Block 1000 writes its diff, checkpoint and block file, then the
+        // node dies before latest.json moves on
+        commit(&reth, STATE_CHECKPOINT_INTERVAL, &[send(1, 1)]);
+        crate::store::write_json(&dir.path().join("latest.json"), &before).unwrap();
+        drop(reth);
+
+        let reth = RethCtx::open(path, ChainOptions::default()).unwrap();
+        assert_eq!(resume_point(&reth), expected);
+        assert_eq!(reth.latest_height().unwrap(), STATE_CHECKPOINT_INTERVAL - 1);
+
+        // CometBFT replays the block, here with a different tx, and the
+        // leftovers of the torn commit are overwritten
+        commit(&reth, STATE_CHECKPOINT_INTERVAL, &[send(1, 2)]);
+        commit(&reth, STATE_CHECKPOINT_INTERVAL + 1, &[send(2, 1)]);
+        let expected = resume_point(&reth);
+        drop(reth);
+
+        let reth = RethCtx::open(path, ChainOptions::default()).unwrap();
+        assert_eq!(resume_point(&reth), expected);
+        let nonce = reth.state.read().unwrap().account(&address(key(1))).unwrap().nonce;
+        assert_eq!(nonce, 3);
+    }
+
+    #[cfg(feature = "with-reth")]
+    #[test]
+    fn set_code_txs_are_refused() {
+        let forks = serde_json::json!({ "shanghaiTime": 0, "cancunTime": 0, "pragueTime": 0 });
+        let (_dir, reth) = node(&genesis(&[key(1)], forks));
+        let set_code = TxEip7702 {
+            chain_id: CHAIN_ID,
+            max_fee_per_gas: 2 * BASE_FEE,
+            gas_limit: 100_000,
+            ..Default::default()
+        };
+        let tx = sign(key(1), Transaction::Eip7702(set_code));
+
+        let err = reth.check_tx(&tx).unwrap_err();
+        assert!(err.to_string().contains("Unsupported tx type 4"), "{}", err);
+        assert!(reth.validate_proposal(&[raw(&tx)], block(1)).is_err());
+        let mut exec = BlockExec::new(&reth, block(1));
+        assert!(matches!(exec.apply_tx(&reth, tx), Err(ExecError::Tx(_))));
+    }
+}
@@ -1,28 +1,145 @@
 use anyhow::Result;
+use clap::Parser;
 use tracing::{info, error};
 use tracing_subscriber::{EnvFilter, fmt};
 use tendermint_abci::ServerBuilder;
 
 mod app;
+mod chain;
+mod cli;
+mod config;
 mod exec;
+#[cfg(feature = "with-reth")]
+mod mempool;
+mod query;
+#[cfg(feature = "with-reth")]
+mod rpc;
+#[cfg(feature = "with-reth")]
+mod snapshot;
+#[cfg(feature = "with-reth")]
+mod state;
+mod store;
+#[cfg(all(test, feature = "with-reth"))]
+mod testutil;
 mod wire;
 
 use crate::app::EvmAbciApp;
+use crate::cli::{Cli, Command, SnapshotCommand};
+use crate::config::{Config, LogFormat, DEFAULT_CONFIG_PATH};
+use crate::exec::RethCtx;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize logging with default INFO level if RUST_LOG not set
-    fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| EnvFilter::new("info"))
-        )
-        .init();
+    let cli = Cli::parse();
+    let mut cfg = Config::load(cli.config.as_deref())?;
 
+    match cli.command {
+        Command::Start(args) => {
+            args.apply(&mut cfg);
+            init_logging(&cfg);
+            start(cfg).await
+        }
+        Command::Init(args) => {
+            args.node.apply(&mut cfg);
+            let path = cli.config.unwrap_or_else(|| DEFAULT_CONFIG_PATH.into());
+            if path.exists() && !args.force {
+                anyhow::bail!("{} already exists (use --force to overwrite)", path.display());
+            }
+            std::fs::create_dir_all(&cfg.data_dir)?;
+            cfg.write(&path)?;
+            println!("Wrote {} (data dir: {})", path.display(), cfg.data_dir);
+            Ok(())
+        }
+        Command::Export(args) => {
+            args.node.apply(&mut cfg);
+            let reth = RethCtx::open(&cfg.data_dir, cfg.chain)?;
+            let genesis = reth.export_genesis()?;
+            match args.out {
+                Some(path) => std::fs::write(path, genesis)?,
+                None => println!("{}", String::from_utf8_lossy(&genesis)),
+            }
+            Ok(())
+        }
+        Command::Snapshot(command) => snapshot_command(command, cfg),
+        Command::Version => {
+            let mode = if cfg!(feature = "with-reth") { "with-reth" } else { "stub" };
+            println!("abci-node {} ({})", env!("CARGO_PKG_VERSION"), mode);
+            println!("{}", include_str!("../../../VERSION.txt").trim());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "with-reth")]
+fn snapshot_command(command: SnapshotCommand, mut cfg: Config) -> Result<()> {
+    use crate::snapshot::SnapshotManager;
+
+    let open = |cfg: &Config| SnapshotManager::open(&cfg.data_dir, cfg.snapshot.clone());
+    match command {
+        SnapshotCommand::List(args) => {
+            args.node.apply(&mut cfg);
+            for info in open(&cfg)?.store.list()? {
+                println!(
+                    "{}\tformat {}\t{} chunks\t0x{}",
+                    info.height,
+                    info.format,
+                    info.chunk_hashes.len(),
+                    hex::encode(info.hash())
+                );
+            }
+        }
+        SnapshotCommand::Export(args) => {
+            args.node.apply(&mut cfg);
+            let manager = open(&cfg)?;
+            let height = match args.height {
+                Some(height) => height,
+                None => match manager.store.list()?.first() {
+                    Some(info) => info.height,
+                    None => anyhow::bail!("No snapshots in {}", cfg.data_dir),
+                },
+            };
+            let info = manager.export(height, &args.out)?;
+            println!("Exported snapshot {} to {}", info.height, args.out.display());
+        }
+        SnapshotCommand::Import(args) => {
+            args.node.apply(&mut cfg);
+            let info = open(&cfg)?.import(&args.file, &cfg.chain)?;
+            println!(
+                "Imported snapshot {} ({} chunks) into {}",
+                info.height,
+                info.chunk_hashes.len(),
+                cfg.data_dir
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "with-reth"))]
+fn snapshot_command(_command: SnapshotCommand, _cfg: Config) -> Result<()> {
+    anyhow::bail!("Snapshots require the with-reth feature")
+}
+
+fn init_logging(cfg: &Config) {
+    // RUST_LOG wins over the configured filter
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(&cfg.log.filter));
+    match cfg.log.format {
+        LogFormat::Text => fmt().with_env_filter(filter).init(),
+        LogFormat::Json => fmt().json().with_env_filter(filter).init(),
+    }
+}
+
+async fn start(cfg: Config) -> Result<()> {
     info!("Starting ABCI server for Reth-CometBFT integration");
 
+    #[cfg(not(feature = "with-reth"))]
+    if cfg.rpc.enabled {
+        tracing::warn!("JSON-RPC requires the with-reth feature - ignoring [rpc] config");
+    }
+
     // Open Reth context (db, txpool, chain config)
-    let app = match EvmAbciApp::boot("./data/reth") {
+    let app = match EvmAbciApp::boot(&cfg.data_dir, cfg.chain, cfg.snapshot, cfg.pruning) {
         Ok(app) => {
             info!("Successfully initialized Reth context");
             app
@@ -33,12 +150,18 @@ async fn main() -> Result<()> {
         }
     };
 
+    #[cfg(feature = "with-reth")]
+    let rpc = match cfg.rpc.enabled {
+        true => Some(rpc::serve(&cfg.rpc, app.clone()).await?),
+        false => None,
+    };
+
     // Start ABCI server
-    info!("Starting ABCI server on 127.0.0.1:26658");
-    
+    info!("Starting ABCI server on {}", cfg.listen_addr);
+
     // Handle graceful shutdown
     let (tx, rx) = tokio::sync::oneshot::channel();
-    
+
     tokio::spawn(async move {
         tokio::signal::ctrl_c()
             .await
@@ -48,16 +171,16 @@ async fn main() -> Result<()> {
     });
 
     // ABCI over TCP for CometBFT's proxy_app
-    let server = ServerBuilder::new(1024)
-        .bind("127.0.0.1:26658", app)?;
-    
+    let server = ServerBuilder::new(cfg.read_buf_size)
+        .bind(&cfg.listen_addr, app)?;
+
         tokio::select! {
             _ = async {
                 // Run the blocking server.listen() in a separate thread
                 let server_result = tokio::task::spawn_blocking(move || {
                     server.listen()
                 }).await.unwrap();
-                
+
                 if let Err(e) = server_result {
                     error!("ABCI server error: {}", e);
                     // Handle error
@@ -68,6 +191,11 @@ async fn main() -> Result<()> {
             }
         }
 
+    #[cfg(feature = "with-reth")]
+    if let Some(handle) = rpc {
+        handle.stop().ok();
+    }
+
     info!("ABCI server stopped");
     Ok(())
-}
\ No newline at end of file
+}
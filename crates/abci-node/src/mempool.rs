@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use reth::primitives::{Address, B256, U256};
+
+use crate::state::WorldState;
+
+#[derive(Default)]
+struct PendingSender {
+    // Nonce the sender's next tx must carry
+    next_nonce: u64,
+    // Sum of gas_limit * max_fee + value over the sender's pending txs
+    reserved: U256,
+}
+
+// What CheckTx has admitted since the last commit, per sender. Committed
+// state plus this is the view new txs are validated against; it is rebuilt
+// from scratch after every commit.
+//
+// Recovered senders are cached by tx hash across commits so ReCheck can skip
+// signature recovery. The cache is generational: a commit moves it to
+// `prev_senders`, and only txs rechecked in that round are carried over, so
+// anything CometBFT dropped from its mempool falls out after one height.
+#[derive(Default)]
+pub struct PendingPool {
+    senders: HashMap<Address, PendingSender>,
+    sender_cache: HashMap<B256, Address>,
+    prev_senders: HashMap<B256, Address>,
+}
+
+impl PendingPool {
+    // Validate nonce and balance for a tx from `sender`, and reserve its
+    // nonce and cost if it passes.
+    pub fn admit(&mut self, state: &WorldState, sender: Address, nonce: u64, cost: U256) -> Result<()> {
+        let (state_nonce, balance) = state
+            .account(&sender)
+            .map(|a| (a.nonce, a.balance))
+            .unwrap_or_default();
+        let pending = self.senders.get(&sender);
+        let expected = pending.map_or(state_nonce, |p| p.next_nonce.max(state_nonce));
+        let reserved = pending.map_or(U256::ZERO, |p| p.reserved);
+
+        if nonce < state_nonce {
+            bail!("nonce too low: got {}, account nonce is {}", nonce, state_nonce);
+        }
+        if nonce < expected {
+            bail!("nonce {} is already pending for {}", nonce, sender);
+        }
+        if nonce > expected {
+            bail!("nonce too high: got {}, expected {}", nonce, expected);
+        }
+
+        let available = balance.saturating_sub(reserved);
+        if available < cost {
+            bail!("insufficient funds: tx costs {} but only {} is available", cost, available);
+        }
+
+        let entry = self.senders.entry(sender).or_default();
+        entry.next_nonce = nonce + 1;
+        entry.reserved += cost;
+        Ok(())
+    }
+
+    // ReCheck of a tx admitted before the last commit. CometBFT may recheck
+    // a sender's txs in any order, so a gap here is no reason to evict; only
+    // txs the commit used the nonce of, or that the sender can no longer
+    // pay for, are.
+    pub fn readmit(&mut self, state: &WorldState, sender: Address, nonce: u64, cost: U256) -> Result<()> {
+        let (state_nonce, balance) = state
+            .account(&sender)
+            .map(|a| (a.nonce, a.balance))
+            .unwrap_or_default();
+        if nonce < state_nonce {
+            bail!("nonce too low: got {}, account nonce is {}", nonce, state_nonce);
+        }
+
+        let entry = self.senders.entry(sender).or_default();
+        let available = balance.saturating_sub(entry.reserved);
+        if available < cost {
+            bail!("insufficient funds: tx costs {} but only {} is available", cost, available);
+        }
+        entry.next_nonce = entry.next_nonce.max(nonce + 1);
+        entry.reserved += cost;
+        Ok(())
+    }
+
+    pub fn remember_sender(&mut self, tx_hash: B256, sender: Address) {
+        self.sender_cache.insert(tx_hash, sender);
+    }
+
+    // Sender of a tx admitted in an earlier round, carried into this round
+    pub fn cached_sender(&mut self, tx_hash: &B256) -> Option<Address> {
+        let sender = self.prev_senders.remove(tx_hash)?;
+        self.sender_cache.insert(*tx_hash, sender);
+        Some(sender)
+    }
+
+    pub fn reset(&mut self) {
+        self.senders.clear();
+        self.prev_senders = std::mem::take(&mut self.sender_cache);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Account;
+
+    fn funded(sender: Address, nonce: u64, balance: u64) -> WorldState {
+        let mut state = WorldState::default();
+        let account = Account { nonce, balance: U256::from(balance), ..Default::default() };
+        state.accounts.insert(sender, account);
+        state
+    }
+
+    #[test]
+    fn admits_consecutive_nonces_and_rejects_gaps() {
+        let sender = Address::with_last_byte(1);
+        let state = funded(sender, 5, 1_000);
+        let mut pool = PendingPool::default();
+
+        pool.admit(&state, sender, 5, U256::from(10)).unwrap();
+        pool.admit(&state, sender, 6, U256::from(10)).unwrap();
+
+        let err = pool.admit(&state, sender, 8, U256::from(10)).unwrap_err();
+        assert!(err.to_string().contains("nonce too high"), "{}", err);
+        let err = pool.admit(&state, sender, 4, U256::from(10)).unwrap_err();
+        assert!(err.to_string().contains("nonce too low"), "{}", err);
+
+        // The gap is gone once the missing nonce arrives
+        pool.admit(&state, sender, 7, U256::from(10)).unwrap();
+        pool.admit(&state, sender, 8, U256::from(10)).unwrap();
+    }
+
+    #[test]
+    fn rejects_replacing_a_pending_nonce() {
+        let sender = Address::with_last_byte(1);
+        let state = funded(sender, 0, 1_000);
+        let mut pool = PendingPool::default();
+
+        pool.admit(&state, sender, 0, U256::from(10)).unwrap();
+        let err = pool.admit(&state, sender, 0, U256::from(20)).unwrap_err();
+        assert!(err.to_string().contains("already pending"), "{}", err);
+
+        // After a commit the pending view is rebuilt and the nonce is free
+        pool.reset();
+        pool.admit(&state, sender, 0, U256::from(20)).unwrap();
+    }
+
+    #[test]
+    fn readmits_rechecks_in_any_nonce_order() {
+        let sender = Address::with_last_byte(1);
+        let state = funded(sender, 5, 100);
+        let mut pool = PendingPool::default();
+
+        pool.readmit(&state, sender, 7, U256::from(30)).unwrap();
+        pool.readmit(&state, sender, 5, U256::from(30)).unwrap();
+        pool.readmit(&state, sender, 6, U256::from(30)).unwrap();
+
+        // Committed nonces and what the balance no longer covers go
+        let err = pool.readmit(&state, sender, 4, U256::ZERO).unwrap_err();
+        assert!(err.to_string().contains("nonce too low"), "{}", err);
+        let err = pool.readmit(&state, sender, 8, U256::from(30)).unwrap_err();
+        assert!(err.to_string().contains("insufficient funds"), "{}", err);
+
+        // New txs queue up behind the rechecked ones
+        pool.admit(&state, sender, 8, U256::from(10)).unwrap();
+    }
+
+    #[test]
+    fn reserves_cost_of_pending_txs() {
+        let sender = Address::with_last_byte(1);
+        let state = funded(sender, 0, 100);
+        let mut pool = PendingPool::default();
+
+        pool.admit(&state, sender, 0, U256::from(60)).unwrap();
+        let err = pool.admit(&state, sender, 1, U256::from(60)).unwrap_err();
+        assert!(err.to_string().contains("insufficient funds"), "{}", err);
+        pool.admit(&state, sender, 1, U256::from(40)).unwrap();
+
+        // An unknown sender has nothing to spend
+        let stranger = Address::with_last_byte(2);
+        assert!(pool.admit(&state, stranger, 0, U256::from(1)).is_err());
+        pool.admit(&state, stranger, 0, U256::ZERO).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{bail, Result};
+use tendermint_proto::crypto::{ProofOp, ProofOps};
+
+use crate::exec::RethCtx;
+
+#[cfg(feature = "with-reth")]
+use reth::primitives::{Address, U256};
+
+// Answer to an ABCI query, at the height it was served from
+pub struct Answer {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    pub height: i64,
+    pub proof_ops: Option<ProofOps>,
+}
+
+// Query paths:
+//   /eth/base_fee                  next block's base fee, 32-byte big-endian
+//   /eth/balance/{addr}            32-byte big-endian wei
+//   /eth/nonce/{addr}              32-byte big-endian
+//   /eth/code/{addr}               raw bytecode
+//   /eth/storage/{addr}/{slot}     32-byte slot value
+//   /eth/receipt/{txhash}          receipt JSON
+//   /eth/block/{height}            block metadata JSON
+// `height` 0 means the latest committed block. Account and storage queries
+// carry Merkle proofs when `prove` is set; see the README for the format.
+pub fn route(reth: &RethCtx, path: &str, height: i64, prove: bool) -> Result<Answer> {
+    let latest = reth.latest_height()?;
+    let height = match height {
+        0 => latest,
+        h if h > latest => bail!("Height {} is not committed yet (latest {})", h, latest),
+        h => h,
+    };
+
+    let parts: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match parts.as_slice() {
+        ["eth", "base_fee"] => {
+            let base_fee = match reth.store.block(height)? {
+                Some(meta) => meta.next_base_fee,
+                None => reth.base_fee(),
+            };
+            let mut value = [0u8; 32];
+            value[24..].copy_from_slice(&base_fee.to_be_bytes());
+            Ok(Answer { key: vec![], value: value.to_vec(), height, proof_ops: None })
+        }
+        ["eth", "receipt", hash] => {
+            let hash = parse_hash(hash)?;
+            let location = match reth.store.tx_location(&hash)? {
+                Some(location) if location.height <= height => location,
+                _ => bail!("Receipt not found"),
+            };
+            let receipts = reth.store.receipts(location.height)?.unwrap_or_default();
+            let Some(receipt) = receipts.get(location.index as usize) else {
+                bail!("Receipt not found");
+            };
+            Ok(Answer {
+                key: hash.to_vec(),
+                value: serde_json::to_vec(receipt)?,
+                height: location.height,
+                proof_ops: None,
+            })
+        }
+        ["eth", "block", number] => {
+            let number: i64 = number.parse()?;
+            let meta = match reth.store.block(number)? {
+                Some(meta) if number <= height => meta,
+                _ => bail!("Block {} not found", number),
+            };
+            Ok(Answer {
+                key: number.to_be_bytes().to_vec(),
+                value: serde_json::to_vec(&meta)?,
+                height: number,
+                proof_ops: None,
+            })
+        }
+        ["eth", field @ ("balance" | "nonce" | "code"), address] => {
+            account_query(reth, height, field, address, None, prove)
+        }
+        ["eth", "storage", address, slot] => {
+            account_query(reth, height, "storage", address, Some(slot), prove)
+        }
+        _ => bail!("Unknown query path {}", path),
+    }
+}
+
+#[cfg(feature = "with-reth")]
+fn account_query(
+    reth: &RethCtx,
+    height: i64,
+    field: &str,
+    address: &str,
+    slot: Option<&str>,
+    prove: bool,
+) -> Result<Answer> {
+    let address: Address = address.parse()?;
+    let slot: Option<U256> = slot.map(str::parse).transpose()?;
+
+    // The live state stays read-locked until the proof is built, so it
+    // can't move past `height` in between
+    reth.with_state_at(height, |state| {
+        let account = state.account(&address);
+        let value = match (field, slot) {
+            ("balance", _) => word(account.map_or(U256::ZERO, |a| a.balance)),
+            ("nonce", _) => word(U256::from(account.map_or(0, |a| a.nonce))),
+            ("code", _) => account.map(|a| a.code.to_vec()).unwrap_or_default(),
+            (_, Some(slot)) => {
+                word(account.and_then(|a| a.storage.get(&slot).copied()).unwrap_or_default())
+            }
+            _ => bail!("Unknown account field {}", field),
+        };
+
+        let proof_ops = match prove {
+            true => {
+                // Storage proof -> storage root in the account leaf -> state
+                // root, which with the receipts root and context hash hashes
+                // to the app hash
+                let (receipts_root, context_hash) = reth.app_hash_parts(height)?;
+                let mut ops = vec![];
+                if let Some(slot) = slot {
+                    ops.push(ProofOp {
+                        r#type: "eth:storage".into(),
+                        key: slot.to_be_bytes::<32>().to_vec(),
+                        data: alloy_rlp::encode(state.storage_proof(&address, slot)),
+                    });
+                }
+                ops.push(ProofOp {
+                    r#type: "eth:account".into(),
+                    key: address.to_vec(),
+                    data: alloy_rlp::encode(state.account_proof(&address)),
+                });
+                ops.push(ProofOp {
+                    r#type: "eth:app_hash".into(),
+                    key: vec![],
+                    data: [receipts_root, context_hash].concat(),
+                });
+                Some(ProofOps { ops })
+            }
+            false => None,
+        };
+
+        Ok(Answer { key: address.to_vec(), value, height, proof_ops })
+    })
+}
+
+#[cfg(not(feature = "with-reth"))]
+fn account_query(
+    _reth: &RethCtx,
+    _height: i64,
+    _field: &str,
+    _address: &str,
+    _slot: Option<&str>,
+    _prove: bool,
+) -> Result<Answer> {
+    bail!("State queries require the with-reth feature")
+}
+
+#[cfg(feature = "with-reth")]
+fn word(value: U256) -> Vec<u8> {
+    value.to_be_bytes::<32>().to_vec()
+}
+
+fn parse_hash(s: &str) -> Result<[u8; 32]> {
+    let bytes = hex::decode(s.trim_start_matches("0x"))?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Expected a 32-byte hash"))
+}
+
+#[cfg(all(test, feature = "with-reth"))]
+mod tests {
+    use super::*;
+    use crate::testutil::*;
+    use crate::wire::apphash_from;
+    use reth::primitives::{keccak256, Bytes, B256};
+    use reth_trie_common::{proof::verify_proof, Nibbles};
+
+    fn nodes(op: &ProofOp) -> Vec<Bytes> {
+        alloy_rlp::decode_exact(&op.data).unwrap()
+    }
+
+    #[test]
+    fn storage_proofs_verify_up_to_the_app_hash() {
+        let contract = Address::with_last_byte(0xee);
+        let mut genesis = with_code(genesis(&[key(1)], serde_json::json!({})), contract, &[0x00]);
+        genesis["alloc"][contract.to_string()]["storage"] = serde_json::json!({
+            format!("{:#066x}", 1): format!("{:#066x}", 0x2a),
+            format!("{:#066x}", 2): format!("{:#066x}", 0x2b),
+        });
+        let (_dir, reth) = node(&genesis);
+        let meta = commit(&reth, 1, &[raw(&transfer(key(1), 0, 2 * BASE_FEE, 1, 21_000))]);
+
+        let path = format!("/eth/storage/{}/1", contract);
+        let answer = route(&reth, &path, 0, true).unwrap();
+        assert_eq!(answer.value, word(U256::from(0x2a)));
+        let ops = answer.proof_ops.unwrap().ops;
+        let types: Vec<_> = ops.iter().map(|op| op.r#type.as_str()).collect();
+        assert_eq!(types, ["eth:storage", "eth:account", "eth:app_hash"]);
+
+        // The slot's value against the storage root in the account leaf
+        let leaf = reth.state.read().unwrap().account(&contract).unwrap().trie_account();
+        let slot = Nibbles::unpack(keccak256(B256::from_slice(&ops[0].key)));
+        let value = alloy_rlp::encode(U256::from(0x2a));
+        verify_proof(leaf.storage_root, slot, Some(value), &nodes(&ops[0])).unwrap();
+
+        // The account leaf against the state root the block committed to
+        let account = Nibbles::unpack(keccak256(&ops[1].key));
+        let encoded = alloy_rlp::encode(leaf);
+        let state_root = B256::from(meta.state_root);
+        verify_proof(state_root, account, Some(encoded), &nodes(&ops[1])).unwrap();
+
+        // And the state root, receipts root and context hash to the app hash
+        let (receipts_root, context_hash) = ops[2].data.split_at(32);
+        let app_hash = apphash_from(
+            meta.state_root,
+            receipts_root.try_into().unwrap(),
+            context_hash.try_into().unwrap(),
+        );
+        assert_eq!(app_hash, meta.app_hash);
+    }
+}
@@ -0,0 +1,688 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use jsonrpsee::core::client::ClientT;
+use jsonrpsee::core::params::ObjectParams;
+use jsonrpsee::http_client::{HttpClient, HttpClientBuilder};
+use jsonrpsee::server::{RpcModule, Server, ServerHandle};
+use jsonrpsee::types::{ErrorObjectOwned, Params};
+use reth::chainspec::EthChainSpec;
+use reth::primitives::{keccak256, Address, Bytes, B256, U256};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::app::EvmAbciApp;
+use crate::config::RpcConfig;
+use crate::exec::{hex0x, BlockInfo, CallRequest, Log, Receipt, RethCtx, Reverted, StateOverrides};
+use crate::state::Account;
+use crate::store::BlockMeta;
+use crate::wire::decode_eth_tx;
+
+// JSON-RPC 2.0 error codes
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+// geth's code for a reverted eth_call; the revert data goes in `data`
+const EXECUTION_REVERTED: i32 = 3;
+
+// Most blocks one eth_feeHistory call covers, as in geth
+const MAX_FEE_HISTORY: u64 = 1024;
+
+// keccak256(rlp([])): there are never uncles
+const EMPTY_OMMERS_HASH: &str = "0x1dcc4de8dec75d7aab85b567b6ccd41ad312451b948a7413f0a142fd40d49347";
+
+type RpcResult<T> = std::result::Result<T, ErrorObjectOwned>;
+
+struct RpcCtx {
+    app: EvmAbciApp,
+    // CometBFT's own RPC, the only way into its mempool
+    cometbft: HttpClient,
+    // Most gas eth_call and eth_estimateGas may use; 0 leaves only the block
+    // gas limit
+    gas_cap: u64,
+}
+
+// broadcast_tx_sync result: CheckTx's code and log
+#[derive(Deserialize)]
+struct BroadcastResult {
+    code: u32,
+    #[serde(default)]
+    log: String,
+}
+
+// Ethereum JSON-RPC over HTTP. Reads are served from committed state and raw
+// txs are relayed to CometBFT's mempool. Block numbers are CometBFT heights
+// and block hashes are CometBFT block hashes; a block is final once
+// committed, so "safe" and "finalized" mean "latest".
+pub async fn serve(cfg: &RpcConfig, app: EvmAbciApp) -> Result<ServerHandle> {
+    let server = Server::builder().build(&cfg.listen_addr).await?;
+    let cometbft = HttpClientBuilder::default().build(&cfg.cometbft_url)?;
+    let mut module = RpcModule::new(RpcCtx { app, cometbft, gas_cap: cfg.gas_cap });
+
+    method(&mut module, "web3_clientVersion", client_version)?;
+    method(&mut module, "web3_sha3", sha3)?;
+    method(&mut module, "net_version", net_version)?;
+    method(&mut module, "net_listening", |_, _| Ok(json!(true)))?;
+    method(&mut module, "eth_chainId", chain_id)?;
+    method(&mut module, "eth_syncing", |_, _| Ok(json!(false)))?;
+    method(&mut module, "eth_accounts", |_, _| Ok(json!([])))?;
+    method(&mut module, "eth_blockNumber", block_number)?;
+    method(&mut module, "eth_gasPrice", gas_price)?;
+    method(&mut module, "eth_maxPriorityFeePerGas", |_, _| Ok(quantity(0u64)))?;
+    method(&mut module, "eth_feeHistory", fee_history)?;
+    method(&mut module, "eth_getBalance", get_balance)?;
+    method(&mut module, "eth_getTransactionCount", get_transaction_count)?;
+    method(&mut module, "eth_getCode", get_code)?;
+    method(&mut module, "eth_getStorageAt", get_storage_at)?;
+    method(&mut module, "eth_getBlockByNumber", get_block_by_number)?;
+    method(&mut module, "eth_getBlockByHash", get_block_by_hash)?;
+    method(&mut module, "eth_getTransactionReceipt", get_transaction_receipt)?;
+    module.register_blocking_method("eth_call", |params, ctx, _| {
+        call(&ctx.app.reth(), params, ctx.gas_cap)
+    })?;
+    module.register_blocking_method("eth_estimateGas", |params, ctx, _| {
+        estimate_gas(&ctx.app.reth(), params, ctx.gas_cap)
+    })?;
+    module.register_async_method("eth_sendRawTransaction", |params, ctx, _| async move {
+        send_raw_transaction(&ctx, params).await
+    })?;
+
+    info!(
+        "Serving Ethereum JSON-RPC on {} (txs go to {})",
+        server.local_addr()?,
+        cfg.cometbft_url
+    );
+    Ok(server.start(module))
+}
+
+// Handlers take the app's RethCtx as of the call. They run on the blocking
+// pool since they take the app lock and read from disk.
+fn method<F>(module: &mut RpcModule<RpcCtx>, name: &'static str, handler: F) -> Result<()>
+where
+    F: Fn(&RethCtx, Params) -> RpcResult<Value> + Clone + Send + Sync + 'static,
+{
+    module.register_blocking_method(name, move |params, ctx, _| handler(&ctx.app.reth(), params))?;
+    Ok(())
+}
+
+fn client_version(_: &RethCtx, _: Params) -> RpcResult<Value> {
+    Ok(json!(format!("abci-node/{}", env!("CARGO_PKG_VERSION"))))
+}
+
+fn sha3(_: &RethCtx, params: Params) -> RpcResult<Value> {
+    let data: Bytes = params.sequence().next()?;
+    Ok(json!(keccak256(&data)))
+}
+
+fn net_version(reth: &RethCtx, _: Params) -> RpcResult<Value> {
+    Ok(json!(reth.chain_spec.chain().id().to_string()))
+}
+
+fn chain_id(reth: &RethCtx, _: Params) -> RpcResult<Value> {
+    Ok(quantity(reth.chain_spec.chain().id()))
+}
+
+fn block_number(reth: &RethCtx, _: Params) -> RpcResult<Value> {
+    Ok(quantity(reth.latest_height().map_err(server_error)? as u64))
+}
+
+// Legacy txs priced at the next block's base fee; there is no tip market
+// to estimate from, so eth_maxPriorityFeePerGas is 0
+fn gas_price(reth: &RethCtx, _: Params) -> RpcResult<Value> {
+    Ok(quantity(reth.base_fee()))
+}
+
+// [blockCount, newestBlock, rewardPercentiles?]: base fee and gas used
+// ratio of up to blockCount blocks ending at newestBlock, plus the base fee
+// of the block after it. Stops early at the first block pruned or before
+// the chain's first block.
+fn fee_history(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let count: Value = seq.next()?;
+    let newest: String = seq.next()?;
+    let percentiles: Option<Vec<f64>> = seq.optional_next()?;
+
+    let count = block_count(&count)?.min(MAX_FEE_HISTORY);
+    let newest = block_height(reth, Some(newest))?;
+    let latest = reth.latest_height().map_err(server_error)?;
+    if newest > latest {
+        return Err(invalid_params(format!("Block {} is beyond the head block {}", newest, latest)));
+    }
+    if let Some(percentiles) = &percentiles {
+        let in_range = percentiles.iter().all(|p| (0.0..=100.0).contains(p));
+        if !in_range || percentiles.windows(2).any(|w| w[0] > w[1]) {
+            return Err(invalid_params("Reward percentiles must rise from 0 to 100".into()));
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for height in (1..=newest).rev().take(count as usize) {
+        let Some(meta) = reth.store.block(height).map_err(server_error)? else { break };
+        blocks.push(meta);
+    }
+    blocks.reverse();
+    let Some(last) = blocks.last() else {
+        return Ok(json!({
+            "oldestBlock": quantity(0u64),
+            "baseFeePerGas": [],
+            "gasUsedRatio": [],
+        }));
+    };
+
+    let base_fees: Vec<Value> = blocks
+        .iter()
+        .map(|meta| quantity(meta.base_fee))
+        .chain([quantity(last.next_base_fee)])
+        .collect();
+    let gas_used_ratios: Vec<f64> = blocks
+        .iter()
+        .map(|meta| match meta.gas_limit {
+            0 => 0.0,
+            limit => meta.gas_used as f64 / limit as f64,
+        })
+        .collect();
+    let mut history = json!({
+        "oldestBlock": quantity(blocks[0].height as u64),
+        "baseFeePerGas": base_fees,
+        "gasUsedRatio": gas_used_ratios,
+    });
+    if let Some(percentiles) = percentiles {
+        let mut rewards = Vec::with_capacity(blocks.len());
+        for meta in &blocks {
+            let receipts = reth.store.receipts(meta.height).map_err(server_error)?;
+            rewards.push(block_rewards(meta, &receipts.unwrap_or_default(), &percentiles));
+        }
+        history["reward"] = json!(rewards);
+    }
+    Ok(history)
+}
+
+// blockCount is a quantity, though some clients send a plain number
+fn block_count(value: &Value) -> RpcResult<u64> {
+    let count = match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    };
+    count.ok_or_else(|| invalid_params(format!("Bad block count {}", value)))
+}
+
+// Effective tip per gas at each percentile of the block's gas, its txs
+// taken from the lowest tip up, as geth does; 0 for an empty block
+fn block_rewards(meta: &BlockMeta, receipts: &[Receipt], percentiles: &[f64]) -> Vec<Value> {
+    let mut tips: Vec<(u128, u64)> = receipts
+        .iter()
+        .map(|r| (r.effective_gas_price.saturating_sub(meta.base_fee as u128), r.gas_used))
+        .collect();
+    tips.sort_unstable();
+    let total: u64 = tips.iter().map(|(_, gas)| gas).sum();
+
+    percentiles
+        .iter()
+        .map(|p| {
+            let threshold = (total as f64 * p / 100.0) as u64;
+            let mut cumulative = 0;
+            let tip = tips
+                .iter()
+                .find(|(_, gas)| {
+                    cumulative += gas;
+                    cumulative >= threshold
+                })
+                .or(tips.last())
+                .map_or(0, |(tip, _)| *tip);
+            quantity(tip)
+        })
+        .collect()
+}
+
+fn get_balance(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let address: Address = seq.next()?;
+    let tag: Option<String> = seq.optional_next()?;
+    with_account(reth, &address, tag, |account| {
+        quantity(account.map_or(U256::ZERO, |a| a.balance))
+    })
+}
+
+fn get_transaction_count(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let address: Address = seq.next()?;
+    let tag: Option<String> = seq.optional_next()?;
+    with_account(reth, &address, tag, |account| quantity(account.map_or(0, |a| a.nonce)))
+}
+
+fn get_code(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let address: Address = seq.next()?;
+    let tag: Option<String> = seq.optional_next()?;
+    with_account(reth, &address, tag, |account| {
+        json!(account.map(|a| a.code.clone()).unwrap_or_default())
+    })
+}
+
+fn get_storage_at(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let address: Address = seq.next()?;
+    let slot: U256 = seq.next()?;
+    let tag: Option<String> = seq.optional_next()?;
+    with_account(reth, &address, tag, |account| {
+        let value = account
+            .and_then(|a| a.storage.get(&slot).copied())
+            .unwrap_or_default();
+        json!(B256::from(value.to_be_bytes::<32>()))
+    })
+}
+
+// Full tx objects are not stored, so `transactions` always lists hashes
+fn get_block_by_number(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let tag: String = seq.next()?;
+    check_full(seq.optional_next()?)?;
+    let height = block_height(reth, Some(tag))?;
+    match reth.store.block(height).map_err(server_error)? {
+        Some(meta) => block_json(reth, &meta),
+        None => Ok(Value::Null),
+    }
+}
+
+// Blocks only list their tx hashes; asking for full tx objects is an error
+// rather than silently getting hashes back
+fn check_full(full: Option<bool>) -> RpcResult<()> {
+    match full {
+        Some(true) => Err(invalid_params("Full transaction objects are not supported".into())),
+        _ => Ok(()),
+    }
+}
+
+fn get_block_by_hash(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let mut seq = params.sequence();
+    let hash: B256 = seq.next()?;
+    check_full(seq.optional_next()?)?;
+    match reth.store.block_by_hash(&hash.0).map_err(server_error)? {
+        Some(meta) => block_json(reth, &meta),
+        None => Ok(Value::Null),
+    }
+}
+
+fn get_transaction_receipt(reth: &RethCtx, params: Params) -> RpcResult<Value> {
+    let hash: B256 = params.sequence().next()?;
+    let Some(location) = reth.store.tx_location(&hash.0).map_err(server_error)? else {
+        return Ok(Value::Null);
+    };
+    let meta = reth.store.block(location.height).map_err(server_error)?;
+    let receipts = reth.store.receipts(location.height).map_err(server_error)?;
+    match (meta, receipts) {
+        (Some(meta), Some(receipts)) => Ok(receipts
+            .get(location.index as usize)
+            .map_or(Value::Null, |receipt| receipt_json(&meta, receipt))),
+        _ => Ok(Value::Null),
+    }
+}
+
+fn call(reth: &RethCtx, params: Params, gas_cap: u64) -> RpcResult<Value> {
+    let (request, height, overrides) = call_params(reth, params, gas_cap)?;
+    let receipt = reth.call(&request, height, &overrides).map_err(server_error)?;
+    if !receipt.success {
+        return Err(reverted(&receipt.output));
+    }
+    Ok(json!(hex0x(&receipt.output)))
+}
+
+fn estimate_gas(reth: &RethCtx, params: Params, gas_cap: u64) -> RpcResult<Value> {
+    let (request, height, overrides) = call_params(reth, params, gas_cap)?;
+    let gas = reth
+        .estimate_gas(&request, height, &overrides)
+        .map_err(|e| match e.downcast_ref::<Reverted>() {
+            Some(revert) => reverted(&revert.output),
+            None => server_error(e),
+        })?;
+    Ok(quantity(gas))
+}
+
+// [tx object, block tag?, state overrides?]. The requested gas (or the
+// block gas limit when none is given) is capped at `gas_cap`.
+fn call_params(
+    reth: &RethCtx,
+    params: Params,
+    gas_cap: u64,
+) -> RpcResult<(CallRequest, i64, StateOverrides)> {
+    let mut seq = params.sequence();
+    let mut request: CallRequest = seq.next()?;
+    let tag: Option<String> = seq.optional_next()?;
+    let overrides: Option<StateOverrides> = seq.optional_next()?;
+    if gas_cap > 0 {
+        let cap = U256::from(gas_cap);
+        request.gas = Some(request.gas.map_or(cap, |gas| gas.min(cap)));
+    }
+    Ok((request, block_height(reth, tag)?, overrides.unwrap_or_default()))
+}
+
+// Reject what CheckTx would reject without state (encoding, chain id,
+// signature) before it reaches CometBFT, then broadcast and wait for
+// CheckTx. Returns the Ethereum tx hash, not CometBFT's.
+async fn send_raw_transaction(ctx: &RpcCtx, params: Params<'_>) -> RpcResult<Value> {
+    let raw: Bytes = params.one()?;
+
+    let app = ctx.app.clone();
+    let checked = raw.clone();
+    let tx = tokio::task::spawn_blocking(move || -> Result<_> {
+        let tx = decode_eth_tx(&checked)?;
+        app.reth().validate_tx_basic(&tx)?;
+        Ok(tx)
+    })
+    .await
+    .map_err(|e| server_error(e.into()))?
+    .map_err(server_error)?;
+
+    let mut args = ObjectParams::new();
+    args.insert("tx", BASE64.encode(&raw))
+        .map_err(|e| server_error(e.into()))?;
+    let result: BroadcastResult = ctx
+        .cometbft
+        .request("broadcast_tx_sync", args)
+        .await
+        .map_err(|e| server_error(anyhow::anyhow!("CometBFT broadcast_tx_sync failed: {}", e)))?;
+    if result.code != 0 {
+        return Err(ErrorObjectOwned::owned(SERVER_ERROR, result.log, None::<()>));
+    }
+
+    Ok(json!(tx.hash()))
+}
+
+// Height for a block tag or hex number
+fn block_height(reth: &RethCtx, tag: Option<String>) -> RpcResult<i64> {
+    let latest = reth.latest_height().map_err(server_error)?;
+    match tag.as_deref().unwrap_or("latest") {
+        "latest" | "pending" | "safe" | "finalized" => Ok(latest),
+        "earliest" => Ok(0),
+        number => number
+            .strip_prefix("0x")
+            .and_then(|hex| i64::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| invalid_params(format!("Bad block number {}", number))),
+    }
+}
+
+fn with_account(
+    reth: &RethCtx,
+    address: &Address,
+    tag: Option<String>,
+    f: impl FnOnce(Option<&Account>) -> Value,
+) -> RpcResult<Value> {
+    let height = block_height(reth, tag)?;
+    reth.with_state_at(height, |state| Ok(f(state.account(address))))
+        .map_err(server_error)
+}
+
+fn block_json(reth: &RethCtx, meta: &BlockMeta) -> RpcResult<Value> {
+    let receipts = reth
+        .store
+        .receipts(meta.height)
+        .map_err(server_error)?
+        .unwrap_or_default();
+    let mut bloom = [0u8; 256];
+    for receipt in &receipts {
+        accrue_bloom(&mut bloom, &receipt.logs);
+    }
+    let mix_hash = BlockInfo {
+        height: meta.height,
+        timestamp: meta.timestamp,
+        proposer: vec![],
+        hash: meta.hash,
+        parent_hash: meta.parent_hash,
+    }
+    .prevrandao();
+    let miner = if meta.coinbase.is_empty() { vec![0u8; 20] } else { meta.coinbase.clone() };
+
+    Ok(json!({
+        "number": quantity(meta.height as u64),
+        "hash": hex0x(&meta.hash),
+        "parentHash": hex0x(&meta.parent_hash),
+        "nonce": "0x0000000000000000",
+        "sha3Uncles": EMPTY_OMMERS_HASH,
+        "logsBloom": hex0x(&bloom),
+        // Not computed; CometBFT's header data_hash commits to the txs
+        "transactionsRoot": hex0x(&[0u8; 32]),
+        "stateRoot": hex0x(&meta.state_root),
+        "receiptsRoot": hex0x(&meta.receipts_root),
+        "miner": hex0x(&miner),
+        "difficulty": "0x0",
+        "totalDifficulty": "0x0",
+        "extraData": "0x",
+        "size": "0x0",
+        "gasLimit": quantity(meta.gas_limit),
+        "gasUsed": quantity(meta.gas_used),
+        "timestamp": quantity(meta.timestamp),
+        "baseFeePerGas": quantity(meta.base_fee),
+        "mixHash": mix_hash,
+        "uncles": [],
+        "transactions": receipts.iter().map(|r| hex0x(&r.tx_hash)).collect::<Vec<_>>(),
+    }))
+}
+
+fn receipt_json(meta: &BlockMeta, receipt: &Receipt) -> Value {
+    let block_hash = hex0x(&meta.hash);
+    let block_number = quantity(meta.height as u64);
+    let tx_hash = hex0x(&receipt.tx_hash);
+    let tx_index = quantity(receipt.tx_index);
+    let logs: Vec<Value> = receipt
+        .logs
+        .iter()
+        .map(|log| {
+            json!({
+                "address": hex0x(&log.address),
+                "topics": log.topics.iter().map(|t| hex0x(t)).collect::<Vec<_>>(),
+                "data": hex0x(&log.data),
+                "blockNumber": block_number,
+                "blockHash": block_hash,
+                "transactionHash": tx_hash,
+                "transactionIndex": tx_index,
+                "logIndex": quantity(log.index),
+                "removed": false,
+            })
+        })
+        .collect();
+    let mut bloom = [0u8; 256];
+    accrue_bloom(&mut bloom, &receipt.logs);
+
+    json!({
+        "transactionHash": tx_hash,
+        "transactionIndex": tx_index,
+        "blockHash": block_hash,
+        "blockNumber": block_number,
+        "from": hex0x(&receipt.from),
+        "to": receipt.to.as_deref().map(hex0x),
+        "cumulativeGasUsed": quantity(receipt.cumulative_gas_used),
+        "gasUsed": quantity(receipt.gas_used),
+        "effectiveGasPrice": quantity(receipt.effective_gas_price),
+        "contractAddress": receipt.contract_address.as_deref().map(hex0x),
+        "logs": logs,
+        "logsBloom": hex0x(&bloom),
+        "status": quantity(receipt.success as u64),
+        "type": quantity(receipt.tx_type),
+    })
+}
+
+// 2048-bit log bloom (yellow paper M3:2048) over each log's address and topics
+fn accrue_bloom(bloom: &mut [u8; 256], logs: &[Log]) {
+    for log in logs {
+        for item in std::iter::once(&log.address).chain(&log.topics) {
+            let hash = keccak256(item);
+            for i in [0, 2, 4] {
+                let bit = (u16::from_be_bytes([hash[i], hash[i + 1]]) & 2047) as usize;
+                bloom[255 - bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+}
+
+fn quantity<T: std::fmt::LowerHex>(value: T) -> Value {
+    json!(format!("{:#x}", value))
+}
+
+fn invalid_params(msg: String) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(INVALID_PARAMS, msg, None::<()>)
+}
+
+fn reverted(output: &[u8]) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(EXECUTION_REVERTED, "execution reverted", Some(hex0x(output)))
+}
+
+fn server_error(e: anyhow::Error) -> ErrorObjectOwned {
+    ErrorObjectOwned::owned(SERVER_ERROR, e.to_string(), None::<()>)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::chain::ChainOptions;
+    use crate::config::{PruningConfig, SnapshotConfig};
+    use crate::testutil::*;
+
+    // A CometBFT RPC whose broadcast_tx_sync answers with `code` and `log`,
+    // recording the base64 tx it was sent
+    async fn mock_cometbft(
+        code: u32,
+        log: &'static str,
+    ) -> (String, ServerHandle, Arc<Mutex<Option<String>>>) {
+        let server = Server::builder().build("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", server.local_addr().unwrap());
+        let sent = Arc::new(Mutex::new(None));
+        let mut module = RpcModule::new(sent.clone());
+        module
+            .register_method("broadcast_tx_sync", move |params, sent, _| -> RpcResult<Value> {
+                let args: Value = params.parse()?;
+                *sent.lock().unwrap() = args["tx"].as_str().map(str::to_owned);
+                Ok(json!({ "code": code, "log": log, "hash": "00" }))
+            })
+            .unwrap();
+        (url, server.start(module), sent)
+    }
+
+    fn ctx(cometbft_url: &str) -> (TempDir, RpcCtx) {
+        let dir = TempDir::new().unwrap();
+        let app = EvmAbciApp::boot(
+            dir.path().to_str().unwrap(),
+            ChainOptions::default(),
+            SnapshotConfig::default(),
+            PruningConfig::default(),
+        )
+        .unwrap();
+        let cometbft = HttpClientBuilder::default().build(cometbft_url).unwrap();
+        (dir, RpcCtx { app, cometbft, gas_cap: 0 })
+    }
+
+    fn raw_tx_params(tx: &[u8]) -> String {
+        json!([hex0x(tx)]).to_string()
+    }
+
+    #[tokio::test]
+    async fn send_raw_transaction_returns_the_eth_tx_hash() {
+        let (url, _server, sent) = mock_cometbft(0, "").await;
+        let (_dir, ctx) = ctx(&url);
+        let tx = transfer(key(1), 0, BASE_FEE, 0, 21_000);
+        let raw = raw(&tx);
+
+        let params = raw_tx_params(&raw);
+        let hash = send_raw_transaction(&ctx, Params::new(Some(&params))).await.unwrap();
+
+        assert_eq!(hash, json!(tx.hash()));
+        assert_eq!(sent.lock().unwrap().as_deref(), Some(BASE64.encode(&raw).as_str()));
+    }
+
+    #[tokio::test]
+    async fn send_raw_transaction_fails_with_the_check_tx_log() {
+        let (url, _server, _) = mock_cometbft(3, "nonce too low").await;
+        let (_dir, ctx) = ctx(&url);
+        let params = raw_tx_params(&raw(&transfer(key(1), 0, BASE_FEE, 0, 21_000)));
+
+        let err = send_raw_transaction(&ctx, Params::new(Some(&params))).await.unwrap_err();
+
+        assert_eq!(err.code(), SERVER_ERROR);
+        assert_eq!(err.message(), "nonce too low");
+    }
+
+    #[tokio::test]
+    async fn send_raw_transaction_fails_when_cometbft_is_unreachable() {
+        // A port nothing listens on
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let (_dir, ctx) = ctx(&format!("http://{}", addr));
+        let params = raw_tx_params(&raw(&transfer(key(1), 0, BASE_FEE, 0, 21_000)));
+
+        let err = send_raw_transaction(&ctx, Params::new(Some(&params))).await.unwrap_err();
+
+        assert_eq!(err.code(), SERVER_ERROR);
+        let message = err.message();
+        assert!(message.starts_with("CometBFT broadcast_tx_sync failed"), "{}", message);
+    }
+
+    // Params of a call to 0x..cc running `code`, via a state override
+    fn call_with_code(code: &str) -> String {
+        let to = Address::with_last_byte(0xcc);
+        json!([{ "to": to }, "latest", { to.to_string(): { "code": code } }]).to_string()
+    }
+
+    #[test]
+    fn estimate_gas_reverts_like_call() {
+        let (_dir, reth) = node(&genesis(&[], json!({})));
+        // mstore(0, 0x2a) revert(0, 32)
+        let params = call_with_code("0x602a60005260206000fd");
+        let revert_data = json!(format!("0x{:064x}", 0x2a)).to_string();
+
+        let call_err = call(&reth, Params::new(Some(&params)), 0).unwrap_err();
+        let estimate_err = estimate_gas(&reth, Params::new(Some(&params)), 0).unwrap_err();
+
+        for err in [call_err, estimate_err] {
+            assert_eq!(err.code(), EXECUTION_REVERTED);
+            assert_eq!(err.message(), "execution reverted");
+            assert_eq!(err.data().map(|data| data.get()), Some(revert_data.as_str()));
+        }
+    }
+
+    #[test]
+    fn fee_history_reports_base_fees_gas_and_tips() {
+        let (_dir, reth) = node(&genesis(&[key(1), key(2)], json!({})));
+        let low = transfer(key(1), 0, 2 * BASE_FEE, 5, 21_000);
+        let high = transfer(key(2), 0, 2 * BASE_FEE, 9, 21_000);
+        let first = commit(&reth, 1, &[raw(&low), raw(&high)]);
+        let second = commit(&reth, 2, &[]);
+
+        let params = json!(["0x3", "latest", [0.0, 50.0, 100.0]]).to_string();
+        let history = fee_history(&reth, Params::new(Some(&params))).unwrap();
+
+        // Only two blocks exist
+        assert_eq!(history["oldestBlock"], json!("0x1"));
+        let base_fees = [first.base_fee, second.base_fee, second.next_base_fee];
+        assert_eq!(history["baseFeePerGas"], json!(base_fees.map(quantity)));
+        let ratio = 42_000.0 / first.gas_limit as f64;
+        assert_eq!(history["gasUsedRatio"], json!([ratio, 0.0]));
+        assert_eq!(history["reward"], json!([["0x5", "0x5", "0x9"], ["0x0", "0x0", "0x0"]]));
+
+        let beyond = json!([1, "0x3"]).to_string();
+        assert!(fee_history(&reth, Params::new(Some(&beyond))).is_err());
+        let unordered = json!([1, "latest", [50.0, 10.0]]).to_string();
+        assert!(fee_history(&reth, Params::new(Some(&unordered))).is_err());
+    }
+
+    #[test]
+    fn calls_are_capped_at_the_gas_cap() {
+        let (_dir, reth) = node(&genesis(&[], json!({})));
+        // mstore(0, gas()) return(0, 32)
+        let params = call_with_code("0x5a60005260206000f3");
+
+        let gas_left = |gas_cap| {
+            let output = call(&reth, Params::new(Some(&params)), gas_cap).unwrap();
+            let output: Bytes = serde_json::from_value(output).unwrap();
+            U256::from_be_slice(&output).to::<u64>()
+        };
+
+        assert!(gas_left(100_000) < 100_000 - 21_000);
+        // Without a cap the call gets the whole block gas limit
+        assert!(gas_left(0) > 100_000);
+    }
+}
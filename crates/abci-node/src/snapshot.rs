@@ -0,0 +1,558 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use reth::primitives::{keccak256, B256};
+use serde::{Deserialize, Serialize};
+use tendermint_proto::abci;
+use tracing::info;
+
+use crate::chain::ChainOptions;
+use crate::config::SnapshotConfig;
+use crate::exec::{context_hash, genesis_hash, load_chain_spec};
+use crate::state::{RecentHashes, WorldState};
+use crate::store::{read_json, write_atomic, write_json, BlockMeta, BlockParams};
+use crate::wire::apphash_from;
+
+// Payload encodings. Bump when SnapshotPayload changes.
+pub const FORMAT_JSON: u32 = 1;
+pub const FORMAT_JSON_GZIP: u32 = 2;
+
+// Header of an exported snapshot file, followed by the format (u32 BE) and
+// the encoded payload
+const EXPORT_MAGIC: &[u8; 8] = b"ABCISNAP";
+
+// Largest payload JSON accepted. Restored payloads come from peers, so
+// decompression stops here instead of running a gzip bomb to the end.
+const MAX_PAYLOAD_SIZE: u64 = 4 << 30;
+
+pub fn is_supported(format: u32) -> bool {
+    matches!(format, FORMAT_JSON | FORMAT_JSON_GZIP)
+}
+
+// Everything a node needs to resume at `meta.height` without replaying
+// blocks. State sync skips InitChain, so the genesis (for the chain spec)
+// and the consensus block params travel with the state, and so do the
+// hashes BLOCKHASH reads from the blocks the node skips.
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotPayload {
+    pub meta: BlockMeta,
+    pub params: BlockParams,
+    #[serde(with = "crate::store::hex_bytes_opt")]
+    pub genesis: Option<Vec<u8>>,
+    pub state: WorldState,
+    pub block_hashes: RecentHashes,
+}
+
+impl SnapshotPayload {
+    pub fn encode(&self, format: u32) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(self)?;
+        match format {
+            FORMAT_JSON => Ok(json),
+            FORMAT_JSON_GZIP => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&json)?;
+                Ok(encoder.finish()?)
+            }
+            _ => bail!("Unsupported snapshot format {}", format),
+        }
+    }
+
+    pub fn decode(bytes: &[u8], format: u32) -> Result<Self> {
+        Self::decode_limited(bytes, format, MAX_PAYLOAD_SIZE)
+    }
+
+    fn decode_limited(bytes: &[u8], format: u32, limit: u64) -> Result<Self> {
+        let json = match format {
+            FORMAT_JSON => bytes.to_vec(),
+            FORMAT_JSON_GZIP => {
+                let mut json = vec![];
+                GzDecoder::new(bytes).take(limit + 1).read_to_end(&mut json)?;
+                json
+            }
+            _ => bail!("Unsupported snapshot format {}", format),
+        };
+        if json.len() as u64 > limit {
+            bail!("Snapshot payload is over {} bytes", limit);
+        }
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    // The state and everything else the node will run on must hash to
+    // `app_hash`, and so must the block it claims to be the state of. The
+    // genesis is taken under `chain_opts`, as the restored node will run it.
+    pub fn verify(&self, app_hash: [u8; 32], chain_opts: &ChainOptions) -> Result<()> {
+        if self.meta.block_params != Some(self.params) {
+            bail!("Snapshot block params do not match its block");
+        }
+        // The genesis must at least parse before it is run
+        load_chain_spec(chain_opts, self.genesis.clone())?;
+        let genesis_hash = genesis_hash(chain_opts, self.genesis.clone())?;
+        let context = context_hash(genesis_hash, &self.meta, &self.block_hashes);
+        if context != self.meta.context_hash {
+            bail!("Snapshot context does not match its block");
+        }
+        let state_root = self.state.state_root().0;
+        let computed = apphash_from(state_root, self.meta.receipts_root, context);
+        if computed != app_hash || computed != self.meta.app_hash {
+            bail!(
+                "Snapshot state hashes to app hash {}, expected {}",
+                hex::encode(computed),
+                hex::encode(app_hash)
+            );
+        }
+        Ok(())
+    }
+}
+
+// A stored or offered snapshot. CometBFT's Snapshot.hash is keccak256 over
+// the concatenated chunk hashes, and Snapshot.metadata carries the chunk
+// hashes themselves so each chunk can be checked as it arrives.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub height: i64,
+    pub format: u32,
+    pub chunk_hashes: Vec<B256>,
+}
+
+impl SnapshotInfo {
+    pub fn hash(&self) -> B256 {
+        keccak256(self.metadata())
+    }
+
+    fn metadata(&self) -> Vec<u8> {
+        self.chunk_hashes.iter().flat_map(|h| h.0).collect()
+    }
+
+    pub fn to_abci(&self) -> abci::Snapshot {
+        abci::Snapshot {
+            height: self.height as u64,
+            format: self.format,
+            chunks: self.chunk_hashes.len() as u32,
+            hash: self.hash().to_vec().into(),
+            metadata: self.metadata().into(),
+        }
+    }
+
+    // Parse a peer's snapshot, checking its metadata against its hash
+    pub fn from_abci(snapshot: &abci::Snapshot) -> Result<Self> {
+        if snapshot.metadata.len() != snapshot.chunks as usize * 32 {
+            bail!("Metadata does not list {} chunk hashes", snapshot.chunks);
+        }
+        let info = Self {
+            height: snapshot.height as i64,
+            format: snapshot.format,
+            chunk_hashes: snapshot.metadata.chunks(32).map(B256::from_slice).collect(),
+        };
+        if info.hash().as_slice() != snapshot.hash.as_ref() {
+            bail!("Snapshot hash does not match its chunk hashes");
+        }
+        Ok(info)
+    }
+}
+
+// Snapshots under <data_dir>/snapshots:
+//   {height}/chunk-{i}       encoded payload, split into chunks
+//   {height}/snapshot.json   SnapshotInfo, written last
+// A directory without snapshot.json is an interrupted write and is ignored.
+#[derive(Clone)]
+pub struct SnapshotStore {
+    root: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn open(data_dir: &str) -> Result<Self> {
+        let root = PathBuf::from(data_dir).join("snapshots");
+        fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+
+    pub fn write(&self, height: i64, format: u32, bytes: &[u8], chunk_size: usize) -> Result<SnapshotInfo> {
+        let dir = self.dir(height);
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+
+        let mut chunk_hashes = vec![];
+        for (i, chunk) in bytes.chunks(chunk_size.max(1)).enumerate() {
+            write_atomic(&dir.join(format!("chunk-{}", i)), chunk)?;
+            chunk_hashes.push(keccak256(chunk));
+        }
+
+        let info = SnapshotInfo { height, format, chunk_hashes };
+        write_json(&dir.join("snapshot.json"), &info)?;
+        Ok(info)
+    }
+
+    pub fn info(&self, height: i64) -> Result<Option<SnapshotInfo>> {
+        read_json(&self.dir(height).join("snapshot.json"))
+    }
+
+    // Complete snapshots, newest first
+    pub fn list(&self) -> Result<Vec<SnapshotInfo>> {
+        let mut infos: Vec<SnapshotInfo> = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            if let Some(info) = read_json(&entry?.path().join("snapshot.json"))? {
+                infos.push(info);
+            }
+        }
+        infos.sort_by_key(|info| std::cmp::Reverse(info.height));
+        Ok(infos)
+    }
+
+    pub fn chunk(&self, height: i64, format: u32, index: u32) -> Result<Option<Vec<u8>>> {
+        match self.info(height)? {
+            Some(info) if info.format == format && (index as usize) < info.chunk_hashes.len() => {
+                Ok(Some(fs::read(self.dir(height).join(format!("chunk-{}", index)))?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // The whole encoded payload
+    pub fn read(&self, height: i64) -> Result<Option<(SnapshotInfo, Vec<u8>)>> {
+        let Some(info) = self.info(height)? else {
+            return Ok(None);
+        };
+        let mut bytes = vec![];
+        for i in 0..info.chunk_hashes.len() {
+            bytes.extend(fs::read(self.dir(height).join(format!("chunk-{}", i)))?);
+        }
+        Ok(Some((info, bytes)))
+    }
+
+    pub fn remove(&self, height: i64) -> Result<()> {
+        // Drop the descriptor first so a half-removed snapshot is never listed
+        let dir = self.dir(height);
+        fs::remove_file(dir.join("snapshot.json"))?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    fn dir(&self, height: i64) -> PathBuf {
+        self.root.join(height.to_string())
+    }
+}
+
+// Takes snapshots per the [snapshot] config. Payloads are encoded and
+// written on a background thread so Commit only pays for copying the state;
+// if the previous snapshot is still being written, the next one is skipped.
+#[derive(Clone)]
+pub struct SnapshotManager {
+    pub store: SnapshotStore,
+    config: SnapshotConfig,
+    busy: Arc<AtomicBool>,
+}
+
+impl SnapshotManager {
+    pub fn open(data_dir: &str, config: SnapshotConfig) -> Result<Self> {
+        if !is_supported(config.format) {
+            bail!("Unsupported snapshot format {}", config.format);
+        }
+        Ok(Self {
+            store: SnapshotStore::open(data_dir)?,
+            config,
+            busy: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn due(&self, height: i64) -> bool {
+        self.config.interval > 0 && height as u64 % self.config.interval == 0
+    }
+
+    pub fn spawn(&self, payload: SnapshotPayload) {
+        let height = payload.meta.height;
+        if self.busy.swap(true, Ordering::SeqCst) {
+            info!("Skipping snapshot at height {}: previous one still being written", height);
+            return;
+        }
+
+        let manager = self.clone();
+        std::thread::spawn(move || {
+            match manager.create(&payload) {
+                Ok(info) => {
+                    info!("Took snapshot at height {} ({} chunks)", height, info.chunk_hashes.len())
+                }
+                Err(e) => info!("Failed to take snapshot at height {}: {}", height, e),
+            }
+            manager.busy.store(false, Ordering::SeqCst);
+        });
+    }
+
+    pub fn create(&self, payload: &SnapshotPayload) -> Result<SnapshotInfo> {
+        let bytes = payload.encode(self.config.format)?;
+        let info = self
+            .store
+            .write(payload.meta.height, self.config.format, &bytes, self.config.chunk_size)?;
+        self.prune()?;
+        Ok(info)
+    }
+
+    // Keep the newest `keep` snapshots, and never fewer than two: peers may
+    // still be fetching the one before the snapshot just written
+    pub fn prune(&self) -> Result<()> {
+        if self.config.keep == 0 {
+            return Ok(());
+        }
+        for info in self.store.list()?.iter().skip(self.config.keep.max(2)) {
+            info!("Pruning snapshot at height {}", info.height);
+            self.store.remove(info.height)?;
+        }
+        Ok(())
+    }
+
+    // Write a stored snapshot as one self-describing file
+    pub fn export(&self, height: i64, out: &Path) -> Result<SnapshotInfo> {
+        let (info, bytes) = self
+            .store
+            .read(height)?
+            .ok_or_else(|| anyhow::anyhow!("No snapshot at height {}", height))?;
+        let mut file = Vec::with_capacity(EXPORT_MAGIC.len() + 4 + bytes.len());
+        file.extend_from_slice(EXPORT_MAGIC);
+        file.extend_from_slice(&info.format.to_be_bytes());
+        file.extend_from_slice(&bytes);
+        write_atomic(out, &file)?;
+        Ok(info)
+    }
+
+    // Add an exported snapshot to the store, re-chunked per the config. Its
+    // state must match the app hash recorded in it.
+    pub fn import(&self, path: &Path, chain_opts: &ChainOptions) -> Result<SnapshotInfo> {
+        let file = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let Some(rest) = file.strip_prefix(EXPORT_MAGIC.as_slice()) else {
+            bail!("{} is not an exported snapshot", path.display());
+        };
+        if rest.len() < 4 {
+            bail!("{} is truncated", path.display());
+        }
+        let (format, bytes) = rest.split_at(4);
+        let format = u32::from_be_bytes(format.try_into().unwrap());
+
+        let payload = SnapshotPayload::decode(bytes, format)?;
+        payload.verify(payload.meta.app_hash, chain_opts)?;
+        self.store
+            .write(payload.meta.height, format, bytes, self.config.chunk_size)
+    }
+}
+
+// A snapshot being restored through state sync
+pub struct Restore {
+    info: SnapshotInfo,
+    // App hash CometBFT's light client verified for the snapshot height
+    app_hash: [u8; 32],
+    chunks: Vec<Option<Vec<u8>>>,
+    // Who sent each chunk, to blame if the assembled state is bad
+    senders: Vec<String>,
+}
+
+impl Restore {
+    pub fn new(info: SnapshotInfo, app_hash: [u8; 32]) -> Self {
+        let chunks = vec![None; info.chunk_hashes.len()];
+        let senders = vec![String::new(); info.chunk_hashes.len()];
+        Self { info, app_hash, chunks, senders }
+    }
+
+    pub fn height(&self) -> i64 {
+        self.info.height
+    }
+
+    // Store a chunk if it matches its advertised hash. Returns whether every
+    // chunk is now in.
+    pub fn add_chunk(&mut self, index: u32, chunk: Vec<u8>, sender: String) -> Result<bool> {
+        let index = index as usize;
+        let Some(expected) = self.info.chunk_hashes.get(index) else {
+            bail!("Chunk {} out of range", index);
+        };
+        if keccak256(&chunk) != *expected {
+            bail!("Chunk {} does not match its hash", index);
+        }
+        self.chunks[index] = Some(chunk);
+        self.senders[index] = sender;
+        Ok(self.chunks.iter().all(Option::is_some))
+    }
+
+    // Distinct senders of the chunks received so far
+    pub fn senders(&self) -> Vec<String> {
+        let mut senders: Vec<String> = self
+            .senders
+            .iter()
+            .filter(|s| !s.is_empty())
+            .cloned()
+            .collect();
+        senders.sort();
+        senders.dedup();
+        senders
+    }
+
+    // Reassemble the payload and check it against the trusted app hash
+    pub fn finish(&self, chain_opts: &ChainOptions) -> Result<SnapshotPayload> {
+        let bytes: Vec<u8> = self.chunks.iter().flatten().flatten().copied().collect();
+        let payload = SnapshotPayload::decode(&bytes, self.info.format)?;
+        if payload.meta.height != self.info.height {
+            bail!("Payload is for height {}, expected {}", payload.meta.height, self.info.height);
+        }
+        payload.verify(self.app_hash, chain_opts)?;
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reth::chainspec::EthChainSpec;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::exec::RethCtx;
+    use crate::testutil::*;
+
+    // A node one transfer past genesis, and the snapshot of that block
+    fn snapshot() -> (TempDir, RethCtx, SnapshotPayload) {
+        let (dir, reth) = node(&genesis(&[key(1)], json!({})));
+        let meta = commit(&reth, 1, &[raw(&transfer(key(1), 0, 2 * BASE_FEE, 1, 21_000))]);
+        let payload = reth.snapshot_payload(&meta).unwrap();
+        (dir, reth, payload)
+    }
+
+    // `payload` written to a store in 100-byte chunks, and a restore of it
+    // against `app_hash`
+    fn offer(payload: &SnapshotPayload, app_hash: [u8; 32]) -> (TempDir, SnapshotStore, Restore) {
+        let dir = TempDir::new().unwrap();
+        let store = SnapshotStore::open(dir.path().to_str().unwrap()).unwrap();
+        let bytes = payload.encode(FORMAT_JSON_GZIP).unwrap();
+        let info = store.write(payload.meta.height, FORMAT_JSON_GZIP, &bytes, 100).unwrap();
+        let offered = SnapshotInfo::from_abci(&info.to_abci()).unwrap();
+        (dir, store, Restore::new(offered, app_hash))
+    }
+
+    fn add_all_chunks(store: &SnapshotStore, restore: &mut Restore) -> bool {
+        let info = store.info(restore.height()).unwrap().unwrap();
+        let mut done = false;
+        for i in 0..info.chunk_hashes.len() as u32 {
+            let chunk = store.chunk(info.height, info.format, i).unwrap().unwrap();
+            done = restore.add_chunk(i, chunk, format!("peer{}", i % 2)).unwrap();
+        }
+        done
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_chunks() {
+        let (_dir, reth, payload) = snapshot();
+        let (_snapshots, store, mut restore) = offer(&payload, payload.meta.app_hash);
+        assert!(store.info(1).unwrap().unwrap().chunk_hashes.len() > 1);
+
+        assert!(add_all_chunks(&store, &mut restore));
+        let restored = restore.finish(&reth.chain_opts).unwrap();
+        assert_eq!(restored.meta.hash, payload.meta.hash);
+        assert_eq!(restored.state.state_root(), payload.state.state_root());
+        assert_eq!(restore.senders(), vec!["peer0".to_string(), "peer1".to_string()]);
+
+        // And a fresh node resumes from it
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let mut synced = RethCtx::open(path, reth.chain_opts.clone()).unwrap();
+        synced.restore_snapshot(restored).unwrap();
+        assert_eq!(synced.latest_height().unwrap(), 1);
+        assert_eq!(synced.base_fee(), payload.meta.next_base_fee);
+        assert_eq!(synced.chain_spec.chain().id(), CHAIN_ID);
+    }
+
+    #[test]
+    fn offered_snapshot_must_match_its_hash() {
+        let (_dir, _reth, payload) = snapshot();
+        let (_snapshots, store, _) = offer(&payload, payload.meta.app_hash);
+        let mut offered = store.info(1).unwrap().unwrap().to_abci();
+        offered.hash = vec![0u8; 32].into();
+        assert!(SnapshotInfo::from_abci(&offered).is_err());
+    }
+
+    #[test]
+    fn restore_rejects_bad_chunks() {
+        let (_dir, _reth, payload) = snapshot();
+        let (_snapshots, store, mut restore) = offer(&payload, payload.meta.app_hash);
+        let mut chunk = store.chunk(1, FORMAT_JSON_GZIP, 0).unwrap().unwrap();
+        chunk[0] ^= 1;
+
+        assert!(restore.add_chunk(0, chunk, "peer".into()).is_err());
+        assert!(restore.add_chunk(1000, vec![], "peer".into()).is_err());
+        assert!(restore.senders().is_empty());
+    }
+
+    #[test]
+    fn restore_rejects_a_wrong_app_hash() {
+        let (_dir, reth, payload) = snapshot();
+        let (_snapshots, store, mut restore) = offer(&payload, [0xff; 32]);
+
+        assert!(add_all_chunks(&store, &mut restore));
+        assert!(restore.finish(&reth.chain_opts).is_err());
+    }
+
+    #[test]
+    fn decode_stops_at_the_size_limit() {
+        let (_dir, _reth, payload) = snapshot();
+        for format in [FORMAT_JSON, FORMAT_JSON_GZIP] {
+            let bytes = payload.encode(format).unwrap();
+            let size = serde_json::to_vec(&payload).unwrap().len() as u64;
+            assert!(SnapshotPayload::decode_limited(&bytes, format, size).is_ok());
+            assert!(SnapshotPayload::decode_limited(&bytes, format, size - 1).is_err());
+        }
+
+        // A bomb: 64 MiB of zeros gzip to a few KiB
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![0u8; 64 << 20]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        let err = SnapshotPayload::decode_limited(&bomb, FORMAT_JSON_GZIP, 1 << 20).unwrap_err();
+        assert!(err.to_string().contains("over"), "{}", err);
+    }
+
+    #[test]
+    fn prune_keeps_the_snapshot_before_the_newest() {
+        let (dir, reth, payload) = snapshot();
+        let config = SnapshotConfig { keep: 1, ..Default::default() };
+        let manager = SnapshotManager::open(dir.path().to_str().unwrap(), config).unwrap();
+        for height in 1..=3 {
+            let mut payload = reth.snapshot_payload(&payload.meta).unwrap();
+            payload.meta.height = height;
+            manager.create(&payload).unwrap();
+        }
+
+        let stored = manager.store.list().unwrap();
+        assert_eq!(stored.iter().map(|info| info.height).collect::<Vec<_>>(), vec![3, 2]);
+    }
+
+    #[test]
+    fn snapshot_context_is_bound_to_the_app_hash() {
+        let (_dir, reth, payload) = snapshot();
+        let app_hash = payload.meta.app_hash;
+        payload.verify(app_hash, &reth.chain_opts).unwrap();
+
+        let tampered: [(&str, fn(&mut SnapshotPayload)); 5] = [
+            ("next base fee", |p| p.meta.next_base_fee += 1),
+            ("block hash", |p| p.meta.hash[0] ^= 1),
+            ("block params", |p| {
+                p.params.max_gas = 1;
+                p.meta.block_params = Some(p.params);
+            }),
+            ("block hashes", |p| p.block_hashes.insert(0, B256::repeat_byte(1))),
+            ("genesis", |p| {
+                let mut genesis: serde_json::Value =
+                    serde_json::from_slice(p.genesis.as_ref().unwrap()).unwrap();
+                genesis["config"]["chainId"] = json!(CHAIN_ID + 1);
+                p.genesis = Some(genesis.to_string().into_bytes());
+            }),
+        ];
+        for (field, tamper) in tampered {
+            let mut bad = reth.snapshot_payload(&payload.meta).unwrap();
+            tamper(&mut bad);
+            // Even with its context hash recomputed to match
+            let genesis_hash = genesis_hash(&reth.chain_opts, bad.genesis.clone()).unwrap();
+            bad.meta.context_hash = context_hash(genesis_hash, &bad.meta, &bad.block_hashes);
+            assert!(bad.verify(app_hash, &reth.chain_opts).is_err(), "tampered {} accepted", field);
+        }
+    }
+}
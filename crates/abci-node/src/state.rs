@@ -0,0 +1,463 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::exec::BLOCKHASH_WINDOW;
+
+use reth::{
+    chainspec::{Genesis, GenesisAccount},
+    primitives::{keccak256, Address, Bytes, B256, U256},
+    revm::{
+        db::{AccountState, CacheDB},
+        primitives::{AccountInfo, Bytecode, KECCAK_EMPTY},
+        DatabaseRef,
+    },
+};
+use reth_trie_common::{
+    proof::ProofRetainer,
+    root::{state_root_unhashed, storage_root_unhashed},
+    HashBuilder, Nibbles, TrieAccount,
+};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Account {
+    pub nonce: u64,
+    pub balance: U256,
+    pub code: Bytes,
+    pub storage: BTreeMap<U256, U256>,
+}
+
+impl Account {
+    pub fn code_hash(&self) -> B256 {
+        if self.code.is_empty() {
+            KECCAK_EMPTY
+        } else {
+            keccak256(&self.code)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nonce == 0 && self.balance.is_zero() && self.code.is_empty() && self.storage.is_empty()
+    }
+
+    pub fn storage_root(&self) -> B256 {
+        storage_root_unhashed(
+            self.storage
+                .iter()
+                .map(|(slot, value)| (B256::from(*slot), *value)),
+        )
+    }
+
+    pub fn trie_account(&self) -> TrieAccount {
+        TrieAccount {
+            nonce: self.nonce,
+            balance: self.balance,
+            storage_root: self.storage_root(),
+            code_hash: self.code_hash(),
+        }
+    }
+
+    fn info(&self) -> AccountInfo {
+        AccountInfo {
+            balance: self.balance,
+            nonce: self.nonce,
+            code_hash: self.code_hash(),
+            code: Some(Bytecode::new_raw(self.code.clone())),
+        }
+    }
+}
+
+// Post-commit EVM state: every account that exists, with its full storage.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WorldState {
+    pub accounts: BTreeMap<Address, Account>,
+    // Block this is the state after. The store keys states by height, so it
+    // is only kept in memory, where it changes under the same lock as the
+    // accounts.
+    #[serde(skip)]
+    pub height: i64,
+    // Trie leaf of each account, storage root included, computed on first
+    // use and dropped when a diff touches the account. A commit only
+    // rehashes the accounts and storage its block changed.
+    #[serde(skip)]
+    trie_accounts: TrieAccounts,
+}
+
+#[derive(Debug, Default)]
+struct TrieAccounts(Mutex<HashMap<Address, TrieAccount>>);
+
+// A clone may diverge from the original, so it gets its own copy
+impl Clone for TrieAccounts {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl WorldState {
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        let accounts = genesis
+            .alloc
+            .iter()
+            .map(|(address, alloc)| {
+                let storage = alloc
+                    .storage
+                    .iter()
+                    .flatten()
+                    .map(|(slot, value)| (U256::from_be_bytes(slot.0), U256::from_be_bytes(value.0)))
+                    .filter(|(_, value)| !value.is_zero())
+                    .collect();
+                let account = Account {
+                    nonce: alloc.nonce.unwrap_or_default(),
+                    balance: alloc.balance,
+                    code: alloc.code.clone().unwrap_or_default(),
+                    storage,
+                };
+                (*address, account)
+            })
+            .collect();
+        Self { accounts, ..Default::default() }
+    }
+
+    pub fn account(&self, address: &Address) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    pub fn storage(&self, address: &Address, slot: &U256) -> U256 {
+        self.account(address)
+            .and_then(|a| a.storage.get(slot).copied())
+            .unwrap_or_default()
+    }
+
+    // Inverse of from_genesis: the current state as a genesis alloc
+    pub fn to_genesis_alloc(&self) -> BTreeMap<Address, GenesisAccount> {
+        self.accounts
+            .iter()
+            .map(|(address, account)| {
+                let storage = account
+                    .storage
+                    .iter()
+                    .map(|(slot, value)| (B256::from(*slot), B256::from(*value)))
+                    .collect::<BTreeMap<_, _>>();
+                let alloc = GenesisAccount {
+                    nonce: Some(account.nonce),
+                    balance: account.balance,
+                    code: (!account.code.is_empty()).then(|| account.code.clone()),
+                    storage: (!storage.is_empty()).then_some(storage),
+                    private_key: None,
+                };
+                (*address, alloc)
+            })
+            .collect()
+    }
+
+    // Ethereum state trie root: keccak(address) -> rlp(nonce, balance, storageRoot, codeHash)
+    pub fn state_root(&self) -> B256 {
+        state_root_unhashed(self.trie_accounts())
+    }
+
+    // State trie nodes from the root down to `address` (or to where it
+    // would be, which proves absence), as in eth_getProof's accountProof
+    pub fn account_proof(&self, address: &Address) -> Vec<Bytes> {
+        let leaves = self
+            .trie_accounts()
+            .into_iter()
+            .map(|(address, leaf)| (keccak256(address), alloy_rlp::encode(leaf)));
+        trie_proof(leaves, keccak256(address))
+    }
+
+    // Every account's trie leaf, from the cache where it has one
+    fn trie_accounts(&self) -> Vec<(Address, TrieAccount)> {
+        let mut cache = self.trie_accounts.0.lock().unwrap();
+        self.accounts
+            .iter()
+            .map(|(address, account)| {
+                let leaf = *cache.entry(*address).or_insert_with(|| account.trie_account());
+                (*address, leaf)
+            })
+            .collect()
+    }
+
+    // Storage trie nodes of `address` down to `slot`, as in storageProof
+    pub fn storage_proof(&self, address: &Address, slot: U256) -> Vec<Bytes> {
+        let storage = self.account(address).map(|account| &account.storage);
+        let leaves = storage
+            .into_iter()
+            .flatten()
+            .map(|(slot, value)| (keccak256(B256::from(*slot)), alloy_rlp::encode(value)));
+        trie_proof(leaves, keccak256(B256::from(slot)))
+    }
+
+    // Fold everything a block touched back into the committed state, and
+    // return it as the block's diff
+    pub fn apply_overlay(&mut self, overlay: CacheDB<StateView>) -> StateDiff {
+        let mut diff = StateDiff::default();
+        let contracts = overlay.contracts;
+        for (address, db_account) in overlay.accounts {
+            let storage_cleared = match db_account.account_state {
+                AccountState::None => continue,
+                AccountState::NotExisting => {
+                    diff.accounts.insert(address, AccountDiff::Deleted);
+                    continue;
+                }
+                AccountState::StorageCleared => true,
+                AccountState::Touched => false,
+            };
+
+            let info = db_account.info;
+            let code = info
+                .code
+                .as_ref()
+                .or_else(|| contracts.get(&info.code_hash))
+                .map(|c| c.original_bytes())
+                .unwrap_or_default();
+            let code_changed = match self.account(&address) {
+                Some(account) => account.code != code,
+                None => !code.is_empty(),
+            };
+
+            let update = AccountDiff::Updated {
+                nonce: info.nonce,
+                balance: info.balance,
+                code: code_changed.then_some(code),
+                storage_cleared,
+                storage: db_account.storage.into_iter().collect(),
+            };
+            diff.accounts.insert(address, update);
+        }
+        self.apply_diff(&diff);
+        diff
+    }
+
+    pub fn apply_diff(&mut self, diff: &StateDiff) {
+        let cache = self.trie_accounts.0.get_mut().unwrap();
+        for address in diff.accounts.keys() {
+            cache.remove(address);
+        }
+
+        for (address, change) in &diff.accounts {
+            let AccountDiff::Updated { nonce, balance, code, storage_cleared, storage } = change else {
+                self.accounts.remove(address);
+                continue;
+            };
+
+            let account = self.accounts.entry(*address).or_default();
+            account.nonce = *nonce;
+            account.balance = *balance;
+            if let Some(code) = code {
+                account.code = code.clone();
+            }
+            if *storage_cleared {
+                account.storage.clear();
+            }
+            for (slot, value) in storage {
+                if value.is_zero() {
+                    account.storage.remove(slot);
+                } else {
+                    account.storage.insert(*slot, *value);
+                }
+            }
+
+            // EIP-161: touched accounts that end up empty are deleted
+            if account.is_empty() {
+                self.accounts.remove(address);
+            }
+        }
+    }
+}
+
+// What one block changed in the world state. The store keeps these per
+// height and full states only at checkpoints; replaying diffs on top of a
+// checkpoint rebuilds any later height.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateDiff {
+    pub accounts: BTreeMap<Address, AccountDiff>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AccountDiff {
+    Deleted,
+    Updated {
+        nonce: u64,
+        balance: U256,
+        // Only when the code changed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<Bytes>,
+        // Storage was wiped before `storage` was written
+        #[serde(default)]
+        storage_cleared: bool,
+        // Slots written by the block; zero deletes
+        storage: BTreeMap<U256, U256>,
+    },
+}
+
+// Rebuild a trie from its hashed-key leaves, keeping the nodes on the path
+// to `target`
+fn trie_proof(leaves: impl Iterator<Item = (B256, Vec<u8>)>, target: B256) -> Vec<Bytes> {
+    let mut leaves: Vec<_> = leaves.map(|(key, value)| (Nibbles::unpack(key), value)).collect();
+    leaves.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let target = Nibbles::unpack(target);
+    let mut builder = HashBuilder::default()
+        .with_proof_retainer(ProofRetainer::from_iter([target.clone()]));
+    for (key, value) in &leaves {
+        builder.add_leaf(key.clone(), value);
+    }
+    builder.root();
+
+    builder
+        .take_proof_nodes()
+        .matching_nodes_sorted(&target)
+        .into_iter()
+        .map(|(_, node)| node)
+        .collect()
+}
+
+// Hashes of the last committed blocks by height: the BLOCKHASH_WINDOW the
+// next block can see, plus the one before it, which a call replayed at the
+// newest height sees
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RecentHashes(BTreeMap<u64, B256>);
+
+impl RecentHashes {
+    pub fn insert(&mut self, height: u64, hash: B256) {
+        self.0.insert(height, hash);
+        let oldest = self.newest().saturating_sub(BLOCKHASH_WINDOW);
+        self.0 = self.0.split_off(&oldest);
+    }
+
+    pub fn get(&self, height: u64) -> Option<B256> {
+        self.0.get(&height).copied()
+    }
+
+    // 0 while no block is committed
+    pub fn newest(&self) -> u64 {
+        self.0.keys().next_back().copied().unwrap_or_default()
+    }
+
+    // keccak256 over each height (u64 BE) and hash, oldest first
+    pub fn digest(&self) -> B256 {
+        let data: Vec<u8> = self
+            .0
+            .iter()
+            .flat_map(|(height, hash)| height.to_be_bytes().into_iter().chain(hash.0))
+            .collect();
+        keccak256(data)
+    }
+}
+
+// Read-only view of the committed state that block overlays are layered on.
+// The commit of the block takes the write lock once the overlay is consumed.
+#[derive(Clone, Default)]
+pub struct StateView {
+    pub state: Arc<RwLock<WorldState>>,
+    pub block_hashes: Arc<RwLock<RecentHashes>>,
+}
+
+impl StateView {
+    pub fn new(state: Arc<RwLock<WorldState>>, block_hashes: Arc<RwLock<RecentHashes>>) -> Self {
+        Self { state, block_hashes }
+    }
+}
+
+impl DatabaseRef for StateView {
+    type Error = Infallible;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self.state.read().unwrap().account(&address).map(Account::info))
+    }
+
+    fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+        // basic_ref always ships the code with the account, so revm never
+        // has to look it up by hash
+        Ok(Bytecode::default())
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        Ok(self.state.read().unwrap().storage(&address, &index))
+    }
+
+    // Zero outside the window, as on Ethereum
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        Ok(self.block_hashes.read().unwrap().get(number).unwrap_or_default())
+    }
+}
+
+// The same over borrowed state, for calls that hold a read lock on the
+// committed state (or own a historical copy) while they run
+#[derive(Clone, Copy)]
+pub struct WorldView<'a> {
+    pub state: &'a WorldState,
+    pub block_hashes: &'a RecentHashes,
+}
+
+impl DatabaseRef for WorldView<'_> {
+    type Error = Infallible;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self.state.account(&address).map(Account::info))
+    }
+
+    fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Ok(Bytecode::default())
+    }
+
+    fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
+        Ok(self.state.storage(&address, &index))
+    }
+
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        Ok(self.block_hashes.get(number).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(nonce: u64, storage: &[(u64, u64)]) -> AccountDiff {
+        AccountDiff::Updated {
+            nonce,
+            balance: U256::from(10),
+            code: None,
+            storage_cleared: false,
+            storage: storage
+                .iter()
+                .map(|(slot, value)| (U256::from(*slot), U256::from(*value)))
+                .collect(),
+        }
+    }
+
+    // The same accounts, hashed from scratch
+    fn uncached_root(state: &WorldState) -> B256 {
+        WorldState { accounts: state.accounts.clone(), ..Default::default() }.state_root()
+    }
+
+    #[test]
+    fn cached_state_root_follows_diffs() {
+        let (a, b) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let mut state = WorldState::default();
+        let mut diff = StateDiff::default();
+        diff.accounts.insert(a, update(1, &[(1, 1), (2, 2)]));
+        diff.accounts.insert(b, update(1, &[(1, 1)]));
+        state.apply_diff(&diff);
+        let root = state.state_root();
+        assert_eq!(root, uncached_root(&state));
+
+        let mut diff = StateDiff::default();
+        diff.accounts.insert(a, update(2, &[(2, 0), (3, 3)]));
+        state.apply_diff(&diff);
+        assert_ne!(state.state_root(), root);
+        assert_eq!(state.state_root(), uncached_root(&state));
+
+        // A copy taken before a diff keeps hashing to what it holds
+        let copy = state.clone();
+        let mut diff = StateDiff::default();
+        diff.accounts.insert(b, AccountDiff::Deleted);
+        state.apply_diff(&diff);
+        assert_eq!(state.state_root(), uncached_root(&state));
+        assert_eq!(copy.state_root(), uncached_root(&copy));
+        assert_ne!(copy.state_root(), state.state_root());
+    }
+}
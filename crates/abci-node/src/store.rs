@@ -0,0 +1,570 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::exec::Receipt;
+#[cfg(feature = "with-reth")]
+use crate::state::{RecentHashes, StateDiff, WorldState};
+
+// Everything the app needs to answer Info after a restart, plus enough
+// per-block data to serve historical lookups.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BlockMeta {
+    pub height: i64,
+    #[serde(with = "hex32")]
+    pub hash: [u8; 32],
+    #[serde(default, with = "hex32")]
+    pub parent_hash: [u8; 32],
+    #[serde(with = "hex32")]
+    pub app_hash: [u8; 32],
+    #[serde(with = "hex32")]
+    pub state_root: [u8; 32],
+    #[serde(with = "hex32")]
+    pub receipts_root: [u8; 32],
+    pub gas_used: u64,
+    pub timestamp: u64,
+    #[serde(default = "initial_base_fee")]
+    pub base_fee: u64,
+    // EIP-1559 base fee the following block must use
+    #[serde(default = "initial_base_fee")]
+    pub next_base_fee: u64,
+    #[serde(default = "default_gas_limit")]
+    pub gas_limit: u64,
+    // EVM address the block's priority fees went to
+    #[serde(default, with = "hex_bytes")]
+    pub coinbase: Vec<u8>,
+    // Block params in force from the next block on, committed atomically
+    // with the block; params.json only holds InitChain's
+    #[serde(default)]
+    pub block_params: Option<BlockParams>,
+    // What the app hash commits to besides the two roots; see
+    // exec::context_hash
+    #[serde(default, with = "hex32")]
+    pub context_hash: [u8; 32],
+    // Ethereum hashes of the block's txs, in order: the keys of its tx index
+    // entries
+    #[serde(default, with = "hex32_vec")]
+    pub tx_hashes: Vec<[u8; 32]>,
+}
+
+// Where a tx landed, keyed by its hash
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TxLocation {
+    pub height: i64,
+    pub index: u64,
+}
+
+// CometBFT's ConsensusParams.block, as last set by InitChain or EndBlock
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockParams {
+    pub max_bytes: i64,
+    // -1 means CometBFT doesn't limit gas
+    pub max_gas: i64,
+}
+
+impl Default for BlockParams {
+    fn default() -> Self {
+        // CometBFT defaults
+        Self { max_bytes: 22_020_096, max_gas: -1 }
+    }
+}
+
+// Heights at which the full world state is written; in between only the
+// block's diff is
+pub const STATE_CHECKPOINT_INTERVAL: i64 = 1000;
+
+// Newest blocks whose tx index entries open() rewrites. The entries are
+// written without fsync, and a crash loses at most what the kernel had not
+// written back yet: seconds of blocks, not a thousand.
+pub const REINDEX_ON_OPEN: i64 = 1000;
+
+// Most heights one prune_below call drops. It runs in Commit, so a long
+// backlog (say, after switching from archive) is worked off over several
+// blocks. One checkpoint interval, so every step reaches a checkpoint.
+pub const MAX_PRUNE_PER_COMMIT: i64 = STATE_CHECKPOINT_INTERVAL;
+
+fn initial_base_fee() -> u64 {
+    crate::exec::INITIAL_BASE_FEE
+}
+
+fn default_gas_limit() -> u64 {
+    crate::exec::DEFAULT_BLOCK_GAS_LIMIT
+}
+
+// Plain-file store under RethCtx::db_path:
+//   genesis.json            app_state_bytes from InitChain, as received
+//   params.json             consensus block params from InitChain
+//   latest.json             pointer to the last committed block
+//   blocks/{height}.json    BlockMeta per height
+//   state/{height}.json     post-block world state at checkpoint heights
+//                           (with-reth); 0 is genesis
+//   diffs/{height}.json     what block `height` changed in the world state
+//   receipts/{height}.json  receipts of the block's txs, in order
+//   txs/{hh}/{hash}.json    TxLocation of a committed tx, by the hash's first
+//                           byte; a lookup cache over the blocks' tx_hashes
+//   hashes/{hash}.json      height of a committed block
+//   base.json               lowest height not yet pruned
+//   restored_hashes.json    BLOCKHASH window of a restored snapshot (with-reth)
+// latest.json is always written last and atomically, so a crash mid-commit
+// leaves the previous height as the recovery point.
+#[derive(Clone)]
+pub struct AppStore {
+    root: PathBuf,
+}
+
+impl AppStore {
+    pub fn open(path: &str) -> Result<Self> {
+        let root = PathBuf::from(path);
+        fs::create_dir_all(root.join("blocks"))?;
+        fs::create_dir_all(root.join("state"))?;
+        fs::create_dir_all(root.join("diffs"))?;
+        fs::create_dir_all(root.join("receipts"))?;
+        fs::create_dir_all(root.join("txs"))?;
+        fs::create_dir_all(root.join("hashes"))?;
+        Ok(Self { root })
+    }
+
+    pub fn latest(&self) -> Result<Option<BlockMeta>> {
+        read_json(&self.root.join("latest.json"))
+    }
+
+    pub fn genesis(&self) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join("genesis.json")) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn put_genesis(&self, raw: &[u8]) -> Result<()> {
+        write_atomic(&self.root.join("genesis.json"), raw)
+    }
+
+    pub fn params(&self) -> Result<Option<BlockParams>> {
+        read_json(&self.root.join("params.json"))
+    }
+
+    pub fn put_params(&self, params: &BlockParams) -> Result<()> {
+        write_json(&self.root.join("params.json"), params)
+    }
+
+    pub fn block(&self, height: i64) -> Result<Option<BlockMeta>> {
+        read_json(&self.block_path(height))
+    }
+
+    pub fn put_block(&self, meta: &BlockMeta) -> Result<()> {
+        write_json(&self.block_path(meta.height), meta)?;
+        write_json(&self.root.join("latest.json"), meta)
+    }
+
+    pub fn block_by_hash(&self, hash: &[u8; 32]) -> Result<Option<BlockMeta>> {
+        match read_json::<i64>(&self.hash_path("hashes", hash))? {
+            Some(height) => self.block(height),
+            None => Ok(None),
+        }
+    }
+
+    pub fn put_block_hash(&self, hash: &[u8; 32], height: i64) -> Result<()> {
+        write_json(&self.hash_path("hashes", hash), &height)
+    }
+
+    pub fn receipts(&self, height: i64) -> Result<Option<Vec<Receipt>>> {
+        read_json(&self.receipts_path(height))
+    }
+
+    pub fn put_receipts(&self, height: i64, receipts: &[Receipt]) -> Result<()> {
+        write_json(&self.receipts_path(height), &receipts)
+    }
+
+    // Where a committed tx landed. An entry only counts if the block it
+    // points to lists the tx there: a crash can leave entries torn, and
+    // pruning the block makes them stale.
+    pub fn tx_location(&self, tx_hash: &[u8; 32]) -> Result<Option<TxLocation>> {
+        let Ok(Some(location)) = read_json::<TxLocation>(&self.tx_path(tx_hash)) else {
+            return Ok(None);
+        };
+        let listed = self
+            .block(location.height)?
+            .and_then(|meta| meta.tx_hashes.get(location.index as usize).copied());
+        Ok((listed == Some(*tx_hash)).then_some(location))
+    }
+
+    // Index the block's txs by hash. The block file holds the same hashes
+    // and is synced, so the entries are not: a block costs one fsync however
+    // many txs it has.
+    pub fn put_tx_locations(&self, meta: &BlockMeta) -> Result<()> {
+        for (index, hash) in meta.tx_hashes.iter().enumerate() {
+            let location = TxLocation { height: meta.height, index: index as u64 };
+            write_unsynced(&self.tx_path(hash), &serde_json::to_vec(&location)?)?;
+        }
+        Ok(())
+    }
+
+    // Rewrite the missing or torn tx index entries of the newest
+    // REINDEX_ON_OPEN blocks up to `height`
+    pub fn reindex_txs(&self, height: i64) -> Result<()> {
+        let oldest = (height - REINDEX_ON_OPEN + 1).max(self.base_height()?);
+        for h in oldest.max(1)..=height {
+            let Some(meta) = self.block(h)? else { continue };
+            for hash in &meta.tx_hashes {
+                if self.tx_location(hash)?.is_none() {
+                    self.put_tx_locations(&meta)?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // World state after block `height`: the newest checkpoint at or below
+    // it, with the diffs of the blocks since replayed on top. A chain
+    // without a genesis state starts from empty at 0.
+    #[cfg(feature = "with-reth")]
+    pub fn state(&self, height: i64) -> Result<Option<WorldState>> {
+        let (checkpoint, mut state) = match self.checkpoint_below(height)? {
+            Some(checkpoint) => {
+                let state = read_json(&self.state_path(checkpoint))?
+                    .with_context(|| format!("Checkpoint {} disappeared", checkpoint))?;
+                (checkpoint, state)
+            }
+            None if self.base_height()? == 0 => (0, WorldState::default()),
+            None => return Ok(None),
+        };
+        for h in checkpoint + 1..=height {
+            let Some(diff) = read_json::<StateDiff>(&self.diff_path(h))? else {
+                return Ok(None);
+            };
+            state.apply_diff(&diff);
+        }
+        state.height = height;
+        Ok(Some(state))
+    }
+
+    // Full world state as a checkpoint
+    #[cfg(feature = "with-reth")]
+    pub fn put_state(&self, height: i64, state: &WorldState) -> Result<()> {
+        write_json(&self.state_path(height), state)
+    }
+
+    #[cfg(feature = "with-reth")]
+    pub fn put_diff(&self, height: i64, diff: &StateDiff) -> Result<()> {
+        write_json(&self.diff_path(height), diff)
+    }
+
+    // Newest checkpoint at or below `height`: every multiple of
+    // STATE_CHECKPOINT_INTERVAL, plus genesis and a restored snapshot
+    #[cfg(feature = "with-reth")]
+    fn checkpoint_below(&self, height: i64) -> Result<Option<i64>> {
+        let base = self.base_height()?;
+        let mut h = height;
+        loop {
+            if self.state_path(h).exists() {
+                return Ok(Some(h));
+            }
+            if h <= base {
+                return Ok(None);
+            }
+            h = ((h - 1) / STATE_CHECKPOINT_INTERVAL * STATE_CHECKPOINT_INTERVAL).max(base);
+        }
+    }
+
+    // Block hashes below the snapshot this node was restored from; the
+    // blocks themselves were never stored here
+    #[cfg(feature = "with-reth")]
+    pub fn restored_hashes(&self) -> Result<Option<RecentHashes>> {
+        read_json(&self.root.join("restored_hashes.json"))
+    }
+
+    #[cfg(feature = "with-reth")]
+    pub fn put_restored_hashes(&self, hashes: &RecentHashes) -> Result<()> {
+        write_json(&self.root.join("restored_hashes.json"), hashes)
+    }
+
+    // Heights below this have been pruned
+    pub fn base_height(&self) -> Result<i64> {
+        Ok(read_json(&self.root.join("base.json"))?.unwrap_or(0))
+    }
+
+    pub fn put_base_height(&self, height: i64) -> Result<()> {
+        write_json(&self.root.join("base.json"), &height)
+    }
+
+    // Delete everything stored for heights below `retain`, at most
+    // MAX_PRUNE_PER_COMMIT of them, returning how many heights were dropped.
+    // base.json only moves once the files are gone, so an interrupted prune
+    // is redone on the next call.
+    pub fn prune_below(&self, retain: i64) -> Result<i64> {
+        let base = self.base_height()?;
+        let retain = retain.min(base + MAX_PRUNE_PER_COMMIT);
+        // States from `retain` on are rebuilt from the checkpoint at or
+        // below it, so pruning stops there
+        #[cfg(feature = "with-reth")]
+        let retain = self.checkpoint_below(retain)?.unwrap_or(0);
+        if retain <= base {
+            return Ok(0);
+        }
+        for height in base..retain {
+            if let Some(meta) = self.block(height)? {
+                for hash in &meta.tx_hashes {
+                    remove_if_exists(&self.tx_path(hash))?;
+                }
+                remove_if_exists(&self.hash_path("hashes", &meta.hash))?;
+            }
+            remove_if_exists(&self.receipts_path(height))?;
+            #[cfg(feature = "with-reth")]
+            remove_if_exists(&self.state_path(height))?;
+            #[cfg(feature = "with-reth")]
+            remove_if_exists(&self.diff_path(height))?;
+            remove_if_exists(&self.block_path(height))?;
+        }
+        self.put_base_height(retain)?;
+        Ok(retain - base)
+    }
+
+    fn block_path(&self, height: i64) -> PathBuf {
+        self.root.join("blocks").join(format!("{}.json", height))
+    }
+
+    fn receipts_path(&self, height: i64) -> PathBuf {
+        self.root.join("receipts").join(format!("{}.json", height))
+    }
+
+    fn hash_path(&self, dir: &str, hash: &[u8; 32]) -> PathBuf {
+        self.root.join(dir).join(format!("{}.json", hex::encode(hash)))
+    }
+
+    // Sharded, so no one directory holds an entry for every tx
+    fn tx_path(&self, hash: &[u8; 32]) -> PathBuf {
+        let hex = hex::encode(hash);
+        self.root.join("txs").join(&hex[..2]).join(format!("{}.json", hex))
+    }
+
+    #[cfg(feature = "with-reth")]
+    fn state_path(&self, height: i64) -> PathBuf {
+        self.root.join("state").join(format!("{}.json", height))
+    }
+
+    #[cfg(feature = "with-reth")]
+    fn diff_path(&self, height: i64) -> PathBuf {
+        self.root.join("diffs").join(format!("{}.json", height))
+    }
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    match fs::read(path) {
+        Ok(bytes) => {
+            let value = serde_json::from_slice(&bytes)
+                .with_context(|| format!("Corrupt store file {}", path.display()))?;
+            Ok(Some(value))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    write_atomic(path, &serde_json::to_vec(value)?)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Write to a temp file and rename it over the target, without fsync: after
+// a crash the file may be missing or empty
+fn write_unsynced(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Write to a temp file, fsync, then rename over the target. The parent
+// directory is fsynced too, or the rename itself may not survive a crash.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+mod hex32 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 32], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<[u8; 32], D::Error> {
+        let s = String::deserialize(d)?;
+        let bytes = hex::decode(s).map_err(D::Error::custom)?;
+        bytes
+            .try_into()
+            .map_err(|_| D::Error::custom("expected 32 bytes"))
+    }
+}
+
+mod hex32_vec {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(items: &[[u8; 32]], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(items.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<[u8; 32]>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .into_iter()
+            .map(|s| {
+                let bytes = hex::decode(s).map_err(D::Error::custom)?;
+                bytes
+                    .try_into()
+                    .map_err(|_| D::Error::custom("expected 32 bytes"))
+            })
+            .collect()
+    }
+}
+
+// Variable-length byte fields, as hex strings
+pub mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        hex::decode(String::deserialize(d)?).map_err(D::Error::custom)
+    }
+}
+
+pub mod hex_bytes_opt {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => s.serialize_some(&hex::encode(bytes)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|s| hex::decode(s).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+pub mod hex_bytes_vec {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(items: &[Vec<u8>], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(items.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .into_iter()
+            .map(|s| hex::decode(s).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn hash(tag: u8, height: i64) -> [u8; 32] {
+        let mut hash = [tag; 32];
+        hash[24..].copy_from_slice(&height.to_be_bytes());
+        hash
+    }
+
+    // A store with the state checkpoints up to `height` (pruning stops at
+    // them) and, at `blocks`, blocks with one indexed tx each and no
+    // receipts, as the stub commits them
+    fn store(height: i64, blocks: &[i64]) -> (TempDir, AppStore) {
+        let dir = TempDir::new().unwrap();
+        let store = AppStore::open(dir.path().to_str().unwrap()).unwrap();
+        #[cfg(feature = "with-reth")]
+        for h in (0..=height).step_by(STATE_CHECKPOINT_INTERVAL as usize) {
+            store.put_state(h, &WorldState::default()).unwrap();
+        }
+        #[cfg(not(feature = "with-reth"))]
+        let _ = height;
+        for &h in blocks {
+            let meta = BlockMeta {
+                height: h,
+                hash: hash(0xb0, h),
+                tx_hashes: vec![hash(0x70, h)],
+                ..Default::default()
+            };
+            store.put_block(&meta).unwrap();
+            store.put_block_hash(&meta.hash, h).unwrap();
+            store.put_tx_locations(&meta).unwrap();
+        }
+        (dir, store)
+    }
+
+    #[test]
+    fn prune_below_is_bounded_per_call() {
+        let (_dir, store) = store(2500, &[]);
+
+        assert_eq!(store.prune_below(2500).unwrap(), MAX_PRUNE_PER_COMMIT);
+        assert_eq!(store.base_height().unwrap(), 1000);
+        assert_eq!(store.prune_below(2500).unwrap(), MAX_PRUNE_PER_COMMIT);
+        assert_eq!(store.base_height().unwrap(), 2000);
+        // The rest stops at the checkpoint at or below the retain height
+        #[cfg(feature = "with-reth")]
+        assert_eq!(store.prune_below(2500).unwrap(), 0);
+        #[cfg(not(feature = "with-reth"))]
+        assert_eq!(store.prune_below(2500).unwrap(), 500);
+    }
+
+    #[test]
+    fn prune_below_drops_the_tx_index_of_pruned_blocks() {
+        let (_dir, store) = store(1200, &[0, 999, 1000, 1200]);
+        store.prune_below(1000).unwrap();
+
+        for h in [0, 999] {
+            assert!(!store.tx_path(&hash(0x70, h)).exists(), "tx of {} kept", h);
+            assert!(store.block_by_hash(&hash(0xb0, h)).unwrap().is_none(), "block {} kept", h);
+        }
+        for h in [1000, 1200] {
+            assert_eq!(store.tx_location(&hash(0x70, h)).unwrap().unwrap().height, h);
+            assert!(store.block_by_hash(&hash(0xb0, h)).unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn tx_index_survives_torn_entries() {
+        let (_dir, store) = store(0, &[1, 2]);
+        let tx = hash(0x70, 2);
+
+        // What a crash before writeback can leave
+        fs::write(store.tx_path(&tx), b"").unwrap();
+        assert!(store.tx_location(&tx).unwrap().is_none());
+        store.reindex_txs(2).unwrap();
+        assert_eq!(store.tx_location(&tx).unwrap().unwrap().height, 2);
+
+        // An entry the block doesn't back is ignored
+        let stray = TxLocation { height: 1, index: 0 };
+        fs::write(store.tx_path(&tx), serde_json::to_vec(&stray).unwrap()).unwrap();
+        assert!(store.tx_location(&tx).unwrap().is_none());
+    }
+}
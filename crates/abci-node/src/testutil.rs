@@ -0,0 +1,121 @@
+// Fixtures shared by the unit tests: signed txs and a node on a scratch
+// data dir
+use reth::primitives::{Address, TxKind, B256, U256};
+use reth_primitives::{sign_message, Transaction, TransactionSigned, TxEip1559};
+use tempfile::TempDir;
+
+use crate::chain::ChainOptions;
+use crate::exec::{BlockInfo, RethCtx};
+use crate::store::BlockMeta;
+use crate::wire::decode_eth_tx;
+
+pub const CHAIN_ID: u64 = 777;
+pub const BASE_FEE: u128 = 1_000_000_000;
+pub const GAS_LIMIT: u64 = 30_000_000;
+
+// Test keys are 1, 2, 3, ...
+pub fn key(n: u8) -> B256 {
+    B256::with_last_byte(n)
+}
+
+pub fn address(key: B256) -> Address {
+    transfer(key, 0, BASE_FEE, 0, 21_000).recover_signer().unwrap()
+}
+
+// A 1-wei EIP-1559 transfer to 0x..ff
+pub fn transfer(key: B256, nonce: u64, max_fee: u128, tip: u128, gas_limit: u64) -> TransactionSigned {
+    let tx = Transaction::Eip1559(TxEip1559 {
+        chain_id: CHAIN_ID,
+        nonce,
+        gas_limit,
+        max_fee_per_gas: max_fee,
+        max_priority_fee_per_gas: tip,
+        to: TxKind::Call(Address::with_last_byte(0xff)),
+        value: U256::from(1),
+        ..Default::default()
+    });
+    sign(key, tx)
+}
+
+// An EIP-1559 call (or contract creation) of `input`, with no value
+pub fn call(key: B256, nonce: u64, to: TxKind, input: &[u8]) -> TransactionSigned {
+    let tx = Transaction::Eip1559(TxEip1559 {
+        chain_id: CHAIN_ID,
+        nonce,
+        gas_limit: 200_000,
+        max_fee_per_gas: 2 * BASE_FEE,
+        max_priority_fee_per_gas: 1,
+        to,
+        input: input.to_vec().into(),
+        ..Default::default()
+    });
+    sign(key, tx)
+}
+
+pub fn sign(key: B256, tx: Transaction) -> TransactionSigned {
+    let signature = sign_message(key, tx.signature_hash()).unwrap();
+    TransactionSigned::from_transaction_and_signature(tx, signature)
+}
+
+pub fn raw(tx: &TransactionSigned) -> Vec<u8> {
+    crate::wire::encode_eth_tx(tx)
+}
+
+// Genesis JSON funding each key with 1000 ETH, with `extra` merged into
+// its chain config
+pub fn genesis(keys: &[B256], extra: serde_json::Value) -> serde_json::Value {
+    let alloc: serde_json::Map<_, _> = keys
+        .iter()
+        .map(|key| {
+            let balance = U256::from(1000u64) * U256::from(10u64).pow(U256::from(18u64));
+            (address(*key).to_string(), serde_json::json!({ "balance": balance }))
+        })
+        .collect();
+    let mut config = serde_json::json!({ "chainId": CHAIN_ID });
+    if let (Some(config), Some(extra)) = (config.as_object_mut(), extra.as_object()) {
+        config.extend(extra.clone());
+    }
+    serde_json::json!({
+        "config": config,
+        "alloc": alloc,
+        "gasLimit": format!("{:#x}", GAS_LIMIT),
+        "baseFeePerGas": format!("{:#x}", BASE_FEE),
+        "difficulty": "0x0",
+    })
+}
+
+// `genesis` with `code` deployed at `address`
+pub fn with_code(mut genesis: serde_json::Value, address: Address, code: &[u8]) -> serde_json::Value {
+    genesis["alloc"][address.to_string()] =
+        serde_json::json!({ "balance": "0x0", "code": format!("0x{}", hex::encode(code)) });
+    genesis
+}
+
+// A node on a fresh data dir, initialised from `genesis`
+pub fn node(genesis: &serde_json::Value) -> (TempDir, RethCtx) {
+    let dir = TempDir::new().unwrap();
+    let mut reth = RethCtx::open(dir.path().to_str().unwrap(), ChainOptions::default()).unwrap();
+    reth.init_genesis(genesis.to_string().as_bytes()).unwrap();
+    (dir, reth)
+}
+
+// Block `height` as BeginBlock would describe it
+pub fn block(height: i64) -> BlockInfo {
+    BlockInfo {
+        height,
+        timestamp: 1_700_000_000 + height as u64,
+        proposer: vec![0xaa; 20],
+        hash: [height as u8; 32],
+        parent_hash: [height.saturating_sub(1) as u8; 32],
+    }
+}
+
+// Execute and commit block `height` with `txs`, as ProcessProposal,
+// DeliverTx and Commit would
+pub fn commit(reth: &RethCtx, height: i64, txs: &[Vec<u8>]) -> BlockMeta {
+    let mut exec = reth.validate_proposal(txs, block(height)).unwrap();
+    for tx in txs {
+        exec.apply_tx(reth, decode_eth_tx(tx).unwrap()).unwrap();
+    }
+    reth.commit_block(exec, reth.block_params()).unwrap()
+}
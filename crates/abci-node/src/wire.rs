@@ -56,14 +56,15 @@ pub fn encode_eth_tx(_tx: &TxEnvelopeAny) -> Vec<u8> {
     vec![]
 }
 
-pub fn apphash_from(state_root: [u8; 32], receipts_root: [u8; 32]) -> [u8; 32] {
+pub fn apphash_from(state_root: [u8; 32], receipts_root: [u8; 32], context_hash: [u8; 32]) -> [u8; 32] {
     #[cfg(feature = "with-reth")]
     {
         use reth::primitives::keccak256;
-        // Proper app hash: keccak256(state_root || receipts_root)
-        let mut data = Vec::with_capacity(64);
+        // Proper app hash: keccak256(state_root || receipts_root || context_hash)
+        let mut data = Vec::with_capacity(96);
         data.extend_from_slice(&state_root);
         data.extend_from_slice(&receipts_root);
+        data.extend_from_slice(&context_hash);
         keccak256(&data).0
     }
     
@@ -72,7 +73,7 @@ pub fn apphash_from(state_root: [u8; 32], receipts_root: [u8; 32]) -> [u8; 32] {
         // Simple XOR for stub (NOT SECURE - only for testing without reth)
         let mut out = [0u8; 32];
         for i in 0..32 {
-            out[i] = state_root[i] ^ receipts_root[i];
+            out[i] = state_root[i] ^ receipts_root[i] ^ context_hash[i];
         }
         out
     }